@@ -0,0 +1,8 @@
+#![no_main]
+use bfkit::{optimizer, parser};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let code = parser::parse_str(String::from_utf8_lossy(data).into_owned());
+    optimizer::optimize(code, 10);
+});
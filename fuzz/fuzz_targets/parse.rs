@@ -0,0 +1,8 @@
+#![no_main]
+use bfkit::parser;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let code: Vec<char> = String::from_utf8_lossy(data).chars().collect();
+    parser::parse(&code);
+});
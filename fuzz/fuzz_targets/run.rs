@@ -0,0 +1,34 @@
+#![no_main]
+use bfkit::generator::{Config, Generator};
+use bfkit::interp::{Interpreter, OutputBuffer};
+use bfkit::{optimizer, parser};
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+
+// Arbitrary Brainfuck programs may loop forever or move off the tape, so the fuzzer input is used
+// to pick a generated program, which is guaranteed to terminate, and the input it reads.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 11 {
+        return;
+    }
+
+    let mut seed = [0u8; 8];
+    seed.copy_from_slice(&data[..8]);
+    let config = Config {
+        length: data[8] as usize,
+        max_depth: (data[9] % 5) as usize,
+        max_iterations: data[10] % 8 + 1,
+        ..Config::default()
+    };
+    let input = data[11..].to_vec();
+
+    let source = Generator::with_config(u64::from_le_bytes(seed), config).generate();
+    let code = parser::parse_str(source);
+
+    for code in vec![code.clone(), optimizer::optimize(code, 10)] {
+        let mut interp = Interpreter::new(code);
+        interp.set_input(Box::new(Cursor::new(input.clone())));
+        interp.set_output(Box::new(OutputBuffer::new()));
+        interp.run();
+    }
+});
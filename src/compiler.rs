@@ -38,11 +38,11 @@ pub fn compile(ir: Vec<BrainfuckInstruction>) -> String {
             }
             BrainfuckInstruction::Read => {
                 indent(&mut result, level);
-                result.push_str(&format!("READ(0)"));
+                result.push_str("READ(0)");
             }
             BrainfuckInstruction::Write => {
                 indent(&mut result, level);
-                result.push_str(&format!("WRITE(0)"));
+                result.push_str("WRITE(0)");
             }
             BrainfuckInstruction::Open => {
                 indent(&mut result, level);
@@ -58,13 +58,18 @@ pub fn compile(ir: Vec<BrainfuckInstruction>) -> String {
                 indent(&mut result, level);
                 result.push_str(&format!("SET(0, {})", value))
             }
-            BrainfuckInstruction::ScanLeft => {
+            BrainfuckInstruction::ScanLeft(stride) => {
                 indent(&mut result, level);
-                result.push_str("SCAN_LEFT()")
+                result.push_str(&format!("SCAN_LEFT({})", stride))
             }
-            BrainfuckInstruction::ScanRight => {
+            BrainfuckInstruction::ScanRight(stride) => {
                 indent(&mut result, level);
-                result.push_str("SCAN_RIGHT()")
+                result.push_str(&format!("SCAN_RIGHT({})", stride))
+            }
+            BrainfuckInstruction::Print(bytes) => {
+                indent(&mut result, level);
+                let escaped: String = bytes.iter().map(|b| format!("\\x{:02x}", b)).collect();
+                result.push_str(&format!("PRINT(\"{}\", {})", escaped, bytes.len()))
             }
         }
         result.push('\n');
@@ -74,7 +79,7 @@ pub fn compile(ir: Vec<BrainfuckInstruction>) -> String {
     let template = include_str!("template.c");
     template
         .replace("__TAPE_SIZE__", "30000")
-        .replace("__CODE__", &result.trim())
+        .replace("__CODE__", result.trim())
 }
 
 #[cfg(test)]
@@ -86,7 +91,7 @@ mod tests {
     #[test]
     fn compile_works() {
         let input = optimizer::optimize(
-            parser::parse_str(String::from("+++[>+++<-],.[>][<][-]")),
+            parser::parse_str(String::from(",+++[>+++<-],.[>][<][-]")),
             10,
         );
 
@@ -99,6 +104,7 @@ mod tests {
 
         let mut expected = String::new();
         for x in vec![
+            "READ(0)",
             "ADJUST(0, 3)",
             "OPEN()",
             "    SELECT(1)",
@@ -108,8 +114,8 @@ mod tests {
             "CLOSE()",
             "READ(0)",
             "WRITE(0)",
-            "SCAN_RIGHT()",
-            "SCAN_LEFT()",
+            "SCAN_RIGHT(1)",
+            "SCAN_LEFT(1)",
             "SET(0, 0)",
         ] {
             expected.push_str(x);
@@ -0,0 +1,361 @@
+//! Code coverage for Brainfuck programs.
+//!
+//! Coverage is collected by running the unoptimized program with profiling turned on, so that
+//! every instruction maps back to a single source character. Each line containing Brainfuck
+//! instructions is reported with the highest execution count of any instruction on it, and each
+//! loop is reported as a branch with two outcomes: entering the body and leaving the loop.
+
+use crate::ir::BrainfuckInstruction;
+use crate::parser::{self, Position};
+use crate::profile::Profile;
+use std::collections::BTreeMap;
+
+/// Coverage of a Brainfuck program accumulated over one or more runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    code: Vec<BrainfuckInstruction>,
+    positions: Vec<Position>,
+    counts: Vec<u64>,
+    iterations: Vec<u64>,
+}
+
+/// Coverage of a single loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    /// The line of the `[` of the loop.
+    pub line: usize,
+    /// The number of times the body of the loop was entered.
+    pub entered: u64,
+    /// The number of times execution left the loop, including skipping it entirely.
+    pub exited: u64,
+}
+
+impl Coverage {
+    /// Creates a new, empty Coverage for some Brainfuck source code.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The Brainfuck source code.
+    pub fn new(source: &str) -> Self {
+        let code = parser::parse_str(source.to_string());
+        let positions = parser::positions(source);
+
+        Self {
+            counts: vec![0; code.len()],
+            iterations: vec![0; code.len()],
+            code,
+            positions,
+        }
+    }
+
+    /// Returns the unoptimized program that must be run to collect coverage.
+    pub fn code(&self) -> &[BrainfuckInstruction] {
+        &self.code
+    }
+
+    /// Adds the counts from a run of the program to the Coverage.
+    ///
+    /// # Arguments
+    ///
+    /// * `profile` - The Profile collected while running `code()`.
+    pub fn add(&mut self, profile: &Profile) {
+        for (total, count) in self.counts.iter_mut().zip(profile.counts.iter()) {
+            *total += count;
+        }
+        for (total, count) in self.iterations.iter_mut().zip(profile.iterations.iter()) {
+            *total += count;
+        }
+    }
+
+    /// Returns the execution count of every line that contains Brainfuck instructions.
+    pub fn lines(&self) -> BTreeMap<usize, u64> {
+        let mut result = BTreeMap::new();
+
+        for (position, count) in self.positions.iter().zip(self.counts.iter()) {
+            let hits = result.entry(position.line).or_insert(0);
+            *hits = (*hits).max(*count);
+        }
+
+        result
+    }
+
+    /// Returns the coverage of every loop, in source order.
+    pub fn branches(&self) -> Vec<Branch> {
+        let mut result = Vec::new();
+        let mut stack = Vec::new();
+
+        for (address, instruction) in self.code.iter().enumerate() {
+            match instruction {
+                BrainfuckInstruction::Open => {
+                    stack.push((address, result.len()));
+                    result.push(Branch {
+                        line: self.positions[address].line,
+                        entered: 0,
+                        exited: 0,
+                    });
+                }
+                BrainfuckInstruction::Close => {
+                    let (open, index) = stack.pop().expect("Got a ] with no matching [");
+                    // Every execution of a bracket either enters the body or leaves the loop.
+                    let entered = self.iterations[open];
+                    result[index].entered = entered;
+                    result[index].exited = self.counts[open] + self.counts[address] - entered;
+                }
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    /// Formats the Coverage as an lcov tracefile.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the source file, as it should appear in the report.
+    pub fn lcov(&self, path: &str) -> String {
+        let lines = self.lines();
+        let branches = self.branches();
+        let mut result = format!("TN:\nSF:{}\n", path);
+
+        for (block, branch) in branches.iter().enumerate() {
+            for (index, taken) in [branch.entered, branch.exited].iter().enumerate() {
+                // A loop that was never reached has neither outcome, which lcov marks with `-`.
+                let taken = if branch.entered == 0 && branch.exited == 0 {
+                    String::from("-")
+                } else {
+                    taken.to_string()
+                };
+                result.push_str(&format!("BRDA:{},{},{},{}\n", branch.line, block, index, taken));
+            }
+        }
+        result.push_str(&format!("BRF:{}\n", branches.len() * 2));
+        result.push_str(&format!("BRH:{}\n", branches_hit(&branches)));
+
+        for (line, hits) in &lines {
+            result.push_str(&format!("DA:{},{}\n", line, hits));
+        }
+        result.push_str(&format!("LF:{}\n", lines.len()));
+        result.push_str(&format!("LH:{}\n", lines.values().filter(|hits| **hits > 0).count()));
+        result.push_str("end_of_record\n");
+
+        result
+    }
+
+    /// Formats the Coverage as a Cobertura XML report.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the source file, as it should appear in the report.
+    /// * `timestamp` - The time the report was generated, in seconds since the Unix epoch.
+    pub fn cobertura(&self, path: &str, timestamp: u64) -> String {
+        let lines = self.lines();
+        let branches = self.branches();
+
+        let lines_covered = lines.values().filter(|hits| **hits > 0).count();
+        let branches_covered = branches_hit(&branches);
+        let line_rate = rate(lines_covered, lines.len());
+        let branch_rate = rate(branches_covered, branches.len() * 2);
+        let path = escape_xml(path);
+
+        let mut result = String::from("<?xml version=\"1.0\" ?>\n");
+        result.push_str(
+            "<!DOCTYPE coverage SYSTEM \"http://cobertura.sourceforge.net/xml/coverage-04.dtd\">\n",
+        );
+        result.push_str(&format!(
+            "<coverage line-rate=\"{}\" branch-rate=\"{}\" lines-covered=\"{}\" lines-valid=\"{}\" branches-covered=\"{}\" branches-valid=\"{}\" complexity=\"0\" version=\"bfkit {}\" timestamp=\"{}\">\n",
+            line_rate,
+            branch_rate,
+            lines_covered,
+            lines.len(),
+            branches_covered,
+            branches.len() * 2,
+            env!("CARGO_PKG_VERSION"),
+            timestamp
+        ));
+        result.push_str("  <sources>\n    <source>.</source>\n  </sources>\n");
+        result.push_str("  <packages>\n");
+        result.push_str(&format!(
+            "    <package name=\".\" line-rate=\"{}\" branch-rate=\"{}\" complexity=\"0\">\n",
+            line_rate, branch_rate
+        ));
+        result.push_str("      <classes>\n");
+        result.push_str(&format!(
+            "        <class name=\"{}\" filename=\"{}\" line-rate=\"{}\" branch-rate=\"{}\" complexity=\"0\">\n",
+            path, path, line_rate, branch_rate
+        ));
+        result.push_str("          <methods/>\n");
+        result.push_str("          <lines>\n");
+
+        for (line, hits) in &lines {
+            let on_line: Vec<&Branch> = branches.iter().filter(|b| b.line == *line).collect();
+            if on_line.is_empty() {
+                result.push_str(&format!(
+                    "            <line number=\"{}\" hits=\"{}\" branch=\"false\"/>\n",
+                    line, hits
+                ));
+            } else {
+                let covered: usize = on_line.iter().map(|b| branch_hits(b)).sum();
+                let valid = on_line.len() * 2;
+                result.push_str(&format!(
+                    "            <line number=\"{}\" hits=\"{}\" branch=\"true\" condition-coverage=\"{}% ({}/{})\"/>\n",
+                    line,
+                    hits,
+                    covered * 100 / valid,
+                    covered,
+                    valid
+                ));
+            }
+        }
+
+        result.push_str("          </lines>\n");
+        result.push_str("        </class>\n");
+        result.push_str("      </classes>\n");
+        result.push_str("    </package>\n");
+        result.push_str("  </packages>\n");
+        result.push_str("</coverage>\n");
+
+        result
+    }
+}
+
+fn branch_hits(branch: &Branch) -> usize {
+    (branch.entered > 0) as usize + (branch.exited > 0) as usize
+}
+
+fn branches_hit(branches: &[Branch]) -> usize {
+    branches.iter().map(branch_hits).sum()
+}
+
+fn rate(covered: usize, valid: usize) -> f64 {
+    if valid == 0 {
+        1.0
+    } else {
+        covered as f64 / valid as f64
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{Interpreter, OutputBuffer};
+    use std::io::Cursor;
+
+    fn run(coverage: &mut Coverage, input: &[u8]) {
+        let mut interp = Interpreter::new(coverage.code().to_vec());
+        interp.set_input(Box::new(Cursor::new(input.to_vec())));
+        interp.set_output(Box::new(OutputBuffer::new()));
+        interp.set_profiling(true);
+        interp.run();
+        coverage.add(interp.profile().unwrap());
+    }
+
+    const SOURCE: &str = "read a byte ,\nif it is nonzero [\n  clear it [-]\n  print it .\n]\nprint +.";
+
+    #[test]
+    fn lines_and_branches_follow_execution() {
+        let mut coverage = Coverage::new(SOURCE);
+        run(&mut coverage, b"\x00");
+
+        assert_eq!(
+            coverage.lines().into_iter().collect::<Vec<_>>(),
+            vec![(1, 1), (2, 1), (3, 0), (4, 0), (5, 0), (6, 1)]
+        );
+        assert_eq!(
+            coverage.branches(),
+            vec![
+                Branch { line: 2, entered: 0, exited: 1 },
+                Branch { line: 3, entered: 0, exited: 0 },
+            ]
+        );
+
+        run(&mut coverage, b"\x02");
+
+        assert_eq!(
+            coverage.lines().into_iter().collect::<Vec<_>>(),
+            vec![(1, 2), (2, 2), (3, 2), (4, 1), (5, 1), (6, 2)]
+        );
+        assert_eq!(
+            coverage.branches(),
+            vec![
+                Branch { line: 2, entered: 1, exited: 2 },
+                Branch { line: 3, entered: 2, exited: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn branches_pair_nested_and_sequential_loops() {
+        let mut coverage = Coverage::new("+[[-]+[-]]\n[+]");
+        run(&mut coverage, b"");
+
+        assert_eq!(
+            coverage.branches(),
+            vec![
+                Branch { line: 1, entered: 1, exited: 1 },
+                Branch { line: 1, entered: 1, exited: 1 },
+                Branch { line: 1, entered: 1, exited: 1 },
+                Branch { line: 2, entered: 0, exited: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn lcov_works() {
+        let mut coverage = Coverage::new(SOURCE);
+        run(&mut coverage, b"\x00");
+
+        assert_eq!(
+            coverage.lcov("test.bf"),
+            [
+                "TN:",
+                "SF:test.bf",
+                "BRDA:2,0,0,0",
+                "BRDA:2,0,1,1",
+                "BRDA:3,1,0,-",
+                "BRDA:3,1,1,-",
+                "BRF:4",
+                "BRH:1",
+                "DA:1,1",
+                "DA:2,1",
+                "DA:3,0",
+                "DA:4,0",
+                "DA:5,0",
+                "DA:6,1",
+                "LF:6",
+                "LH:3",
+                "end_of_record",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn cobertura_works() {
+        let mut coverage = Coverage::new(SOURCE);
+        run(&mut coverage, b"\x00");
+
+        let result = coverage.cobertura("a&b.bf", 42);
+
+        assert!(result.contains(
+            "<coverage line-rate=\"0.5\" branch-rate=\"0.25\" lines-covered=\"3\" lines-valid=\"6\" branches-covered=\"1\" branches-valid=\"4\""
+        ));
+        assert!(result.contains("timestamp=\"42\""));
+        assert!(result.contains("filename=\"a&amp;b.bf\""));
+        assert!(result.contains("<line number=\"1\" hits=\"1\" branch=\"false\"/>"));
+        assert!(result.contains(
+            "<line number=\"2\" hits=\"1\" branch=\"true\" condition-coverage=\"50% (1/2)\"/>"
+        ));
+        assert!(result.contains(
+            "<line number=\"3\" hits=\"0\" branch=\"true\" condition-coverage=\"0% (0/2)\"/>"
+        ));
+    }
+}
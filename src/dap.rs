@@ -0,0 +1,817 @@
+//! A Debug Adapter Protocol server, so that Brainfuck programs can be debugged from any editor that speaks DAP.
+//!
+//! Messages are read from one stream and written to another, normally stdin and stdout, each framed by a
+//! `Content-Length` header. The program has a single thread, whose stack has a frame for the current instruction
+//! and one for every loop it is nested in. Tape cells and the interpreter's pointers are shown as variables.
+//! The unoptimized program is run so that every instruction maps back to a single character of the source.
+
+use crate::expr;
+use crate::interp::{Interpreter, OutputBuffer, StopReason};
+use crate::ir::BrainfuckInstruction;
+use crate::parser::{self, Position};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, BufRead, Cursor, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+/// The number of instructions executed between checks for new requests while the program is running.
+const RUN_CHUNK: u64 = 100_000;
+
+/// The number of steps remembered for `stepBack` and `reverseContinue`.
+const HISTORY_LIMIT: usize = 1_000_000;
+
+/// The id of the only thread.
+const THREAD_ID: i64 = 1;
+
+/// The variablesReference of the tape cells.
+const TAPE_REFERENCE: i64 = 1;
+
+/// The variablesReference of the interpreter's pointers.
+const POINTERS_REFERENCE: i64 = 2;
+
+/// Reads a single message framed by a `Content-Length` header.
+/// Returns `None` at the end of the stream.
+///
+/// # Arguments
+///
+/// * `reader` - Where the message is read from.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(|_| invalid("Invalid Content-Length"))?);
+        }
+    }
+
+    let mut body = vec![0; length.ok_or_else(|| invalid("Missing Content-Length"))?];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| invalid(&e.to_string()))
+}
+
+/// Writes a single message framed by a `Content-Length` header.
+///
+/// # Arguments
+///
+/// * `writer` - Where the message is written.
+/// * `message` - The message to write.
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Serves DAP requests until the client disconnects or the stream of requests ends.
+///
+/// # Arguments
+///
+/// * `reader` - Where requests are read from.
+/// * `writer` - Where responses and events are written.
+pub fn serve(reader: impl BufRead + Send + 'static, writer: impl Write) -> io::Result<()> {
+    // Requests are read on their own thread so that they can be handled while the program is running.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = reader;
+        while let Ok(Some(message)) = read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session::new(writer);
+    while !session.disconnected {
+        let request = if session.running.is_some() {
+            match receiver.try_recv() {
+                Ok(request) => Some(request),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match receiver.recv() {
+                Ok(request) => Some(request),
+                Err(_) => break,
+            }
+        };
+
+        if let Some(request) = request {
+            session.handle(&request)?;
+        }
+        session.tick()?;
+    }
+
+    Ok(())
+}
+
+/// How far a running program is run before it stops by itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Run {
+    /// Runs until a breakpoint or watchpoint is hit or the program finishes.
+    Continue,
+    /// Also stops when the instruction pointer arrives at a code address.
+    Until(usize),
+}
+
+/// A program being debugged.
+struct Program {
+    interp: Interpreter,
+    output: OutputBuffer,
+    path: String,
+    positions: Vec<Position>,
+    stop_on_entry: bool,
+}
+
+/// The state of a connection to a client.
+struct Session<W: Write> {
+    writer: W,
+    seq: i64,
+    program: Option<Program>,
+    lines_start_at_1: bool,
+    columns_start_at_1: bool,
+    /// How the program is being run, if it is running.
+    running: Option<Run>,
+    disconnected: bool,
+}
+
+impl<W: Write> Session<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            seq: 0,
+            program: None,
+            lines_start_at_1: true,
+            columns_start_at_1: true,
+            running: None,
+            disconnected: false,
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.writer, &message)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+
+    fn handle(&mut self, request: &Value) -> io::Result<()> {
+        let command = request["command"].as_str().unwrap_or("").to_string();
+        let arguments = &request["arguments"];
+
+        let result = match command.as_str() {
+            "initialize" => self.initialize(arguments),
+            "launch" => self.launch(arguments),
+            "disconnect" | "terminate" => {
+                self.disconnected = true;
+                Ok(Value::Null)
+            }
+            _ if self.program.is_none() => Err(String::from("No program has been launched")),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({"breakpoints": []})),
+            "configurationDone" => Ok(Value::Null),
+            "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "main"}]})),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(self.scopes()),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => {
+                self.running = Some(Run::Continue);
+                Ok(json!({"allThreadsContinued": true}))
+            }
+            "next" | "stepIn" | "stepOut" | "stepBack" | "reverseContinue" if self.running.is_some() => {
+                Err(String::from("The program is running, pause it first"))
+            }
+            "stepBack" => {
+                if self.program_mut().interp.step_back() {
+                    Ok(Value::Null)
+                } else {
+                    Err(String::from("No more history!"))
+                }
+            }
+            "pause" | "next" | "stepIn" | "stepOut" | "reverseContinue" => Ok(Value::Null),
+            _ => Err(format!("Unrecognized command: {}", command)),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        let success = response["success"] == json!(true);
+        self.send(response)?;
+
+        // Events caused by a request must follow its response.
+        match command.as_str() {
+            "launch" if success => self.event("initialized", json!({})),
+            "configurationDone" if success => {
+                if self.program().stop_on_entry {
+                    self.stopped("entry", None)
+                } else {
+                    self.running = Some(Run::Continue);
+                    Ok(())
+                }
+            }
+            "pause" if self.running.is_some() => {
+                self.running = None;
+                self.stopped("pause", None)
+            }
+            "next" if success => match self.program().interp.step_over_target() {
+                Some(target) => {
+                    self.running = Some(Run::Until(target));
+                    Ok(())
+                }
+                None => {
+                    let reason = self.program_mut().interp.step_many(1);
+                    self.report(reason)
+                }
+            },
+            "stepIn" if success => {
+                let reason = self.program_mut().interp.step_many(1);
+                self.report(reason)
+            }
+            "stepOut" if success => {
+                // Outside of any loop, stepping out runs the program to the end like gdb's `finish` in main.
+                self.running = Some(match self.program().interp.finish_target() {
+                    Ok(target) => Run::Until(target),
+                    Err(_) => Run::Continue,
+                });
+                Ok(())
+            }
+            "stepBack" if success => self.stopped("step", None),
+            "reverseContinue" if success => {
+                let reason = self.program_mut().interp.reverse_run();
+                self.report(reason)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn program(&self) -> &Program {
+        self.program.as_ref().unwrap()
+    }
+
+    fn program_mut(&mut self) -> &mut Program {
+        self.program.as_mut().unwrap()
+    }
+
+    /// Runs the next chunk of the program if it is running.
+    fn tick(&mut self) -> io::Result<()> {
+        let run = match self.running {
+            Some(run) => run,
+            None => return Ok(()),
+        };
+
+        let interp = &mut self.program_mut().interp;
+        let (reason, arrived) = match run {
+            Run::Continue => (interp.step_many(RUN_CHUNK), false),
+            Run::Until(address) => {
+                let reason = interp.step_until(address, RUN_CHUNK);
+                (reason, interp.instruction_pointer() == address)
+            }
+        };
+        if reason != StopReason::Stepped || arrived {
+            self.running = None;
+            self.report(reason)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the events describing why the program stopped, after any output it wrote.
+    fn report(&mut self, reason: StopReason) -> io::Result<()> {
+        let output = self.program().output.take();
+        if !output.is_empty() {
+            let output = String::from_utf8_lossy(&output).into_owned();
+            self.event("output", json!({"category": "stdout", "output": output}))?;
+        }
+
+        match reason {
+            StopReason::Breakpoint(address) => self.stopped("breakpoint", Some(address)),
+            StopReason::Watchpoint(_) => self.stopped("data breakpoint", None),
+            StopReason::Done => {
+                self.event("exited", json!({"exitCode": 0}))?;
+                self.event("terminated", json!({}))
+            }
+            StopReason::Stepped | StopReason::HistoryExhausted => self.stopped("step", None),
+            StopReason::BudgetExhausted | StopReason::Timeout | StopReason::Interrupted => self.stopped("pause", None),
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, breakpoint: Option<usize>) -> io::Result<()> {
+        let mut body = json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true});
+        if let Some(address) = breakpoint {
+            body["hitBreakpointIds"] = json!([address]);
+        }
+        self.event("stopped", body)
+    }
+
+    fn initialize(&mut self, arguments: &Value) -> Result<Value, String> {
+        self.lines_start_at_1 = arguments["linesStartAt1"].as_bool().unwrap_or(true);
+        self.columns_start_at_1 = arguments["columnsStartAt1"].as_bool().unwrap_or(true);
+
+        Ok(json!({
+            "supportsConfigurationDoneRequest": true,
+            "supportsConditionalBreakpoints": true,
+            "supportsHitConditionalBreakpoints": true,
+            "supportsEvaluateForHovers": true,
+            "supportsStepBack": true,
+            "supportsTerminateRequest": true,
+        }))
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["program"].as_str().ok_or("Missing program")?;
+        let source = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let code = parser::parse_str(source.clone());
+        parser::check_brackets(&code)?;
+
+        let output = OutputBuffer::new();
+        let mut interp = Interpreter::new(code);
+        let input = arguments["input"].as_str().unwrap_or("").as_bytes().to_vec();
+        interp.set_input(Box::new(Cursor::new(input)));
+        interp.set_output(Box::new(output.clone()));
+        interp.set_history_limit(HISTORY_LIMIT);
+
+        self.program = Some(Program {
+            interp,
+            output,
+            path: path.to_string(),
+            positions: parser::positions(&source),
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+        });
+        Ok(Value::Null)
+    }
+
+    /// Converts a line or column number from the client's convention to starting at 1.
+    fn from_client(number: i64, starts_at_1: bool) -> usize {
+        (if starts_at_1 { number } else { number + 1 }).max(1) as usize
+    }
+
+    /// Converts a line or column number starting at 1 to the client's convention.
+    fn to_client(number: usize, starts_at_1: bool) -> usize {
+        if starts_at_1 {
+            number
+        } else {
+            number - 1
+        }
+    }
+
+    fn source(&self) -> Value {
+        let path = &self.program().path;
+        let name = Path::new(path).file_name().map_or(path.clone(), |n| n.to_string_lossy().into_owned());
+        json!({"name": name, "path": path})
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let lines_start_at_1 = self.lines_start_at_1;
+        let columns_start_at_1 = self.columns_start_at_1;
+        let program = self.program.as_mut().unwrap();
+
+        let existing: Vec<usize> = program.interp.breakpoints().iter().map(|(address, _)| *address).collect();
+        for address in existing {
+            program.interp.delete_breakpoint(address);
+        }
+
+        let mut result = Vec::new();
+        let requested = arguments["breakpoints"].as_array().cloned().unwrap_or_default();
+        for breakpoint in &requested {
+            let line = Self::from_client(breakpoint["line"].as_i64().unwrap_or(1), lines_start_at_1);
+            let column = breakpoint["column"]
+                .as_i64()
+                .map_or(1, |column| Self::from_client(column, columns_start_at_1));
+
+            // A breakpoint goes on the first instruction at or after the requested position on its line.
+            let address = program
+                .positions
+                .iter()
+                .position(|p| p.line == line && p.column >= column);
+            let address = match address {
+                Some(address) => address,
+                None => {
+                    result.push(json!({"verified": false, "message": format!("No instructions on line {}", line)}));
+                    continue;
+                }
+            };
+
+            let position = program.positions[address];
+            let mut set = || -> Result<(), String> {
+                program.interp.set_breakpoint(address)?;
+                if let Some(condition) = breakpoint["condition"].as_str().filter(|c| !c.trim().is_empty()) {
+                    program.interp.set_breakpoint_condition(address, Some(expr::parse(condition)?))?;
+                }
+                if let Some(hits) = breakpoint["hitCondition"].as_str().filter(|c| !c.trim().is_empty()) {
+                    // Like gdb's `ignore`, a hit count of N passes over the breakpoint N - 1 times.
+                    let hits = hits
+                        .trim()
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|hits| *hits > 0)
+                        .ok_or_else(|| format!("Invalid hit count: {}", hits))?;
+                    program.interp.set_breakpoint_ignore_count(address, hits - 1)?;
+                }
+                Ok(())
+            };
+            match set() {
+                Ok(()) => result.push(json!({
+                    "id": address,
+                    "verified": true,
+                    "line": Self::to_client(position.line, lines_start_at_1),
+                    "column": Self::to_client(position.column, columns_start_at_1),
+                })),
+                Err(message) => {
+                    program.interp.delete_breakpoint(address);
+                    result.push(json!({"verified": false, "message": message}));
+                }
+            }
+        }
+
+        Ok(json!({"breakpoints": result}))
+    }
+
+    fn frame(&self, id: usize, name: String, address: usize) -> Value {
+        match self.program().positions.get(address) {
+            Some(position) => json!({
+                "id": id,
+                "name": name,
+                "source": self.source(),
+                "line": Self::to_client(position.line, self.lines_start_at_1),
+                "column": Self::to_client(position.column, self.columns_start_at_1),
+            }),
+            None => json!({"id": id, "name": name, "line": 0, "column": 0}),
+        }
+    }
+
+    /// Returns a frame for the current instruction, followed by one for every enclosing loop, innermost first.
+    fn stack_trace(&self) -> Value {
+        let interp = &self.program().interp;
+        let instruction_pointer = interp.instruction_pointer();
+
+        let mut loops = Vec::new();
+        for (address, instruction) in interp.code().iter().enumerate().take(instruction_pointer) {
+            match instruction {
+                BrainfuckInstruction::Open => loops.push(address),
+                BrainfuckInstruction::Close => {
+                    loops.pop();
+                }
+                _ => {}
+            }
+        }
+
+        let name = match interp.code().get(instruction_pointer) {
+            Some(instruction) => format!("{} at {}", instruction, instruction_pointer),
+            None => String::from("end of program"),
+        };
+        let mut frames = vec![self.frame(0, name, instruction_pointer)];
+        for (id, address) in loops.iter().rev().enumerate() {
+            frames.push(self.frame(id + 1, format!("loop at {}", address), *address));
+        }
+
+        json!({"stackFrames": frames, "totalFrames": frames.len()})
+    }
+
+    fn scopes(&self) -> Value {
+        json!({"scopes": [
+            {
+                "name": "Tape",
+                "variablesReference": TAPE_REFERENCE,
+                "indexedVariables": self.program().interp.tape().len(),
+                "expensive": false,
+            },
+            {
+                "name": "Pointers",
+                "variablesReference": POINTERS_REFERENCE,
+                "expensive": false,
+            },
+        ]})
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value, String> {
+        let interp = &self.program().interp;
+
+        let variables: Vec<Value> = match arguments["variablesReference"].as_i64() {
+            Some(TAPE_REFERENCE) => {
+                let tape = interp.tape();
+                // Without paging, show the tape up to the last interesting cell.
+                let (start, count) = match arguments["start"].as_u64() {
+                    Some(start) => (start as usize, arguments["count"].as_u64().map_or(tape.len(), |c| c as usize)),
+                    None => {
+                        let last = tape.iter().rposition(|x| *x != 0).unwrap_or(0);
+                        (0, last.max(interp.data_pointer()) + 1)
+                    }
+                };
+
+                tape.iter()
+                    .enumerate()
+                    .skip(start)
+                    .take(count)
+                    .map(|(address, value)| {
+                        let mut text = value.to_string();
+                        if value.is_ascii_graphic() {
+                            text.push_str(&format!(" '{}'", *value as char));
+                        }
+                        json!({"name": address.to_string(), "value": text, "variablesReference": 0})
+                    })
+                    .collect()
+            }
+            Some(POINTERS_REFERENCE) => {
+                let instruction = match interp.code().get(interp.instruction_pointer()) {
+                    Some(instruction) => instruction.to_string(),
+                    None => String::from("end of program"),
+                };
+                vec![
+                    json!({"name": "ip", "value": interp.instruction_pointer().to_string(), "variablesReference": 0}),
+                    json!({"name": "dp", "value": interp.data_pointer().to_string(), "variablesReference": 0}),
+                    json!({"name": "steps", "value": interp.steps().to_string(), "variablesReference": 0}),
+                    json!({"name": "instruction", "value": instruction, "variablesReference": 0}),
+                ]
+            }
+            _ => return Err(format!("Unknown variablesReference: {}", arguments["variablesReference"])),
+        };
+
+        Ok(json!({"variables": variables}))
+    }
+
+    fn evaluate(&self, arguments: &Value) -> Result<Value, String> {
+        let expression = arguments["expression"].as_str().ok_or("Missing expression")?;
+        let value = expr::parse(expression)?.evaluate(&self.program().interp)?;
+        Ok(json!({"result": value.to_string(), "variablesReference": 0}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// Serves a scripted list of requests for a program and returns everything the server sent.
+    fn session(name: &str, source: &str, requests: Vec<Value>) -> Vec<Value> {
+        let path = env::temp_dir().join(format!("bfkit-dap-{}-{}.bf", process::id(), name));
+        fs::write(&path, source).unwrap();
+
+        let mut input = Vec::new();
+        for (seq, mut request) in requests.into_iter().enumerate() {
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            if request["command"] == "launch" {
+                request["arguments"]["program"] = json!(path.to_str().unwrap());
+            }
+            write_message(&mut input, &request).unwrap();
+        }
+        input.extend_from_slice(&{
+            let mut disconnect = Vec::new();
+            write_message(&mut disconnect, &json!({"seq": 0, "type": "request", "command": "disconnect"})).unwrap();
+            disconnect
+        });
+
+        let mut output = Vec::new();
+        serve(Cursor::new(input), &mut output).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut reader = Cursor::new(output);
+        let mut result = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            result.push(message);
+        }
+        result
+    }
+
+    /// Summarizes messages as `response <command>` or `event <name>` to check their order.
+    fn kinds(messages: &[Value]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|m| match m["type"].as_str().unwrap() {
+                "response" => format!("response {}", m["command"].as_str().unwrap()),
+                _ => format!("event {}", m["event"].as_str().unwrap()),
+            })
+            .collect()
+    }
+
+    fn find<'a>(messages: &'a [Value], kind: &str) -> Vec<&'a Value> {
+        messages
+            .iter()
+            .zip(kinds(messages))
+            .filter(|(_, k)| k == kind)
+            .map(|(m, _)| m)
+            .collect()
+    }
+
+    #[test]
+    fn messages_round_trip_through_framing() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &json!({"a": "\u{e9}"})).unwrap();
+
+        assert!(buffer.starts_with(b"Content-Length: 10\r\n\r\n"));
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({"a": "\u{e9}"})));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn breakpoints_stop_the_program_and_output_is_forwarded() {
+        let messages = session(
+            "breakpoints",
+            "++++++++[>++++++++<-]>+.\n>+++[<.>-]",
+            vec![
+                json!({"command": "initialize", "arguments": {}}),
+                json!({"command": "launch", "arguments": {}}),
+                json!({"command": "setBreakpoints", "arguments": {"breakpoints": [
+                    {"line": 2, "column": 3},
+                    {"line": 2, "column": 7, "hitCondition": "2"},
+                    {"line": 3},
+                ]}}),
+                json!({"command": "configurationDone"}),
+                json!({"command": "continue", "arguments": {"threadId": 1}}),
+            ],
+        );
+
+        assert_eq!(
+            kinds(&messages),
+            vec![
+                "response initialize",
+                "response launch",
+                "event initialized",
+                "response setBreakpoints",
+                "response configurationDone",
+                "event output",
+                "event stopped",
+                "response continue",
+                "event output",
+                "event stopped",
+                "response disconnect",
+            ]
+        );
+
+        let breakpoints = &find(&messages, "response setBreakpoints")[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0], json!({"id": 26, "verified": true, "line": 2, "column": 3}));
+        assert_eq!(breakpoints[1], json!({"id": 30, "verified": true, "line": 2, "column": 7}));
+        assert_eq!(breakpoints[2], json!({"verified": false, "message": "No instructions on line 3"}));
+
+        let stopped = find(&messages, "event stopped");
+        assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+        assert_eq!(stopped[0]["body"]["hitBreakpointIds"], json!([26]));
+        assert_eq!(stopped[1]["body"]["hitBreakpointIds"], json!([30]));
+
+        let output = find(&messages, "event output");
+        assert_eq!(output[0]["body"]["output"], "A");
+        assert_eq!(output[1]["body"]["output"], "A");
+    }
+
+    #[test]
+    fn stack_trace_shows_nested_loops_and_variables_show_the_tape() {
+        let messages = session(
+            "stack",
+            "+++[>++\n[>+<-]<-]",
+            vec![
+                json!({"command": "initialize", "arguments": {"linesStartAt1": false}}),
+                json!({"command": "launch", "arguments": {"stopOnEntry": true}}),
+                json!({"command": "configurationDone"}),
+                json!({"command": "setBreakpoints", "arguments": {"breakpoints": [{"line": 1, "column": 3}]}}),
+                json!({"command": "continue"}),
+                json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+                json!({"command": "scopes", "arguments": {"frameId": 0}}),
+                json!({"command": "variables", "arguments": {"variablesReference": 1}}),
+                json!({"command": "variables", "arguments": {"variablesReference": 2}}),
+                json!({"command": "evaluate", "arguments": {"expression": "*1 + *2"}}),
+                json!({"command": "evaluate", "arguments": {"expression": "*1 +"}}),
+            ],
+        );
+
+        assert_eq!(find(&messages, "event stopped")[0]["body"]["reason"], "entry");
+
+        let frames = &find(&messages, "response stackTrace")[0]["body"]["stackFrames"];
+        assert_eq!(frames.as_array().unwrap().len(), 3);
+        assert_eq!(frames[0]["name"], "add 1 at 9");
+        assert_eq!((frames[0]["line"].clone(), frames[0]["column"].clone()), (json!(1), json!(3)));
+        assert_eq!(frames[1]["name"], "loop at 7");
+        assert_eq!((frames[1]["line"].clone(), frames[1]["column"].clone()), (json!(1), json!(1)));
+        assert_eq!(frames[2]["name"], "loop at 3");
+        assert_eq!((frames[2]["line"].clone(), frames[2]["column"].clone()), (json!(0), json!(4)));
+
+        let tape = &find(&messages, "response variables")[0]["body"]["variables"];
+        assert_eq!(tape.as_array().unwrap().len(), 3);
+        assert_eq!(tape[1], json!({"name": "1", "value": "2", "variablesReference": 0}));
+
+        let pointers = &find(&messages, "response variables")[1]["body"]["variables"];
+        assert_eq!(pointers[0], json!({"name": "ip", "value": "9", "variablesReference": 0}));
+        assert_eq!(pointers[1], json!({"name": "dp", "value": "2", "variablesReference": 0}));
+
+        let evaluated = find(&messages, "response evaluate");
+        assert_eq!(evaluated[0]["body"]["result"], "2");
+        assert_eq!(evaluated[1]["success"], false);
+        assert_eq!(evaluated[1]["message"], "Unexpected end of expression");
+    }
+
+    #[test]
+    fn stepping_requests_move_through_the_program() {
+        let messages = session(
+            "stepping",
+            "+[>+[-]<-]>",
+            vec![
+                json!({"command": "initialize", "arguments": {}}),
+                json!({"command": "launch", "arguments": {"stopOnEntry": true}}),
+                json!({"command": "configurationDone"}),
+                json!({"command": "stepIn", "arguments": {"threadId": 1}}),
+                json!({"command": "next", "arguments": {"threadId": 1}}),
+                json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+                json!({"command": "stepBack", "arguments": {"threadId": 1}}),
+                json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+                json!({"command": "stepOut", "arguments": {"threadId": 1}}),
+                json!({"command": "stepOut", "arguments": {"threadId": 1}}),
+                json!({"command": "frobnicate"}),
+            ],
+        );
+
+        let names: Vec<&Value> = find(&messages, "response stackTrace")
+            .iter()
+            .map(|m| &m["body"]["stackFrames"][0]["name"])
+            .collect();
+        assert_eq!(names, vec!["right 1 at 10", "close at 9"]);
+        assert_eq!(find(&messages, "event stopped").len(), 5);
+        assert_eq!(find(&messages, "event terminated").len(), 1);
+        assert_eq!(find(&messages, "response frobnicate")[0]["message"], "Unrecognized command: frobnicate");
+    }
+
+    #[test]
+    fn pause_stops_a_next_request_stuck_in_an_infinite_loop() {
+        let messages = session(
+            "next",
+            "+[]",
+            vec![
+                json!({"command": "initialize", "arguments": {}}),
+                json!({"command": "launch", "arguments": {"stopOnEntry": true}}),
+                json!({"command": "configurationDone"}),
+                json!({"command": "stepIn", "arguments": {"threadId": 1}}),
+                json!({"command": "next", "arguments": {"threadId": 1}}),
+                json!({"command": "pause", "arguments": {"threadId": 1}}),
+            ],
+        );
+
+        assert_eq!(
+            kinds(&messages)[6..],
+            ["event stopped", "response next", "response pause", "event stopped", "response disconnect"]
+        );
+        assert_eq!(find(&messages, "event stopped")[2]["body"]["reason"], "pause");
+    }
+
+    #[test]
+    fn stepping_requests_fail_without_history_or_while_running() {
+        let messages = session(
+            "refused",
+            "+[]",
+            vec![
+                json!({"command": "initialize", "arguments": {}}),
+                json!({"command": "launch", "arguments": {"stopOnEntry": true}}),
+                json!({"command": "configurationDone"}),
+                json!({"command": "stepBack", "arguments": {"threadId": 1}}),
+                json!({"command": "continue", "arguments": {"threadId": 1}}),
+                json!({"command": "stepIn", "arguments": {"threadId": 1}}),
+                json!({"command": "next", "arguments": {"threadId": 1}}),
+                json!({"command": "pause", "arguments": {"threadId": 1}}),
+            ],
+        );
+
+        assert_eq!(
+            kinds(&messages)[5..],
+            [
+                "response stepBack",
+                "response continue",
+                "response stepIn",
+                "response next",
+                "response pause",
+                "event stopped",
+                "response disconnect"
+            ]
+        );
+        let step_back = find(&messages, "response stepBack")[0];
+        assert_eq!(step_back["success"], false);
+        assert_eq!(step_back["message"], "No more history!");
+        for command in ["stepIn", "next"] {
+            let response = find(&messages, &format!("response {}", command))[0];
+            assert_eq!(response["success"], false);
+            assert_eq!(response["message"], "The program is running, pause it first");
+        }
+        assert_eq!(find(&messages, "event stopped")[1]["body"]["reason"], "pause");
+    }
+}
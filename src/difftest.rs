@@ -0,0 +1,337 @@
+//! Differential testing of the optimizer.
+//!
+//! Runs a program through the Interpreter both exactly as parsed and after optimization, and
+//! compares the output, final tape and final data pointer of the two executions.
+
+use crate::interp::{Interpreter, OutputBuffer};
+use crate::ir::BrainfuckInstruction;
+use crate::optimizer;
+use crate::parser;
+use std::any::Any;
+use std::fmt;
+use std::io::Cursor;
+use std::panic::{self, AssertUnwindSafe};
+
+/// The observable results of running a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Execution {
+    /// Everything the program wrote to its output.
+    pub output: Vec<u8>,
+    /// The tape at the point execution stopped.
+    pub tape: Vec<u8>,
+    /// The data pointer at the point execution stopped.
+    pub data_pointer: usize,
+    /// Whether the program ran to completion within the step limit.
+    pub finished: bool,
+    /// The message the Interpreter panicked with, such as when the program moved off the tape.
+    pub crash: Option<String>,
+}
+
+/// Divergence describes the first difference found between two executions of a program.
+#[derive(Debug, PartialEq)]
+pub enum Divergence {
+    /// Output means that the programs wrote different bytes at some offset. `None` means that one of them stopped writing.
+    Output {
+        offset: usize,
+        expected: Option<u8>,
+        actual: Option<u8>,
+    },
+    /// Tape means that the programs finished with different values in some cell.
+    Tape {
+        address: usize,
+        expected: u8,
+        actual: u8,
+    },
+    /// DataPointer means that the programs finished with the data pointer at different cells.
+    DataPointer { expected: usize, actual: usize },
+    /// Crash means that at least one of the programs crashed the Interpreter. `None` means that one of them did not.
+    Crash {
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn byte(value: &Option<u8>) -> String {
+            match value {
+                Some(value) => value.to_string(),
+                None => String::from("end of output"),
+            }
+        }
+
+        fn crash(message: &Option<String>) -> String {
+            match message {
+                Some(message) => format!("crash ({})", message),
+                None => String::from("no crash"),
+            }
+        }
+
+        match self {
+            Divergence::Output {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Output differs at byte {}: expected {}, got {}",
+                offset,
+                byte(expected),
+                byte(actual)
+            ),
+            Divergence::Tape {
+                address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Tape differs at cell {}: expected {}, got {}",
+                address, expected, actual
+            ),
+            Divergence::DataPointer { expected, actual } => write!(
+                f,
+                "Data pointer differs: expected {}, got {}",
+                expected, actual
+            ),
+            Divergence::Crash { expected, actual } => write!(
+                f,
+                "Program crashed: expected {}, got {}",
+                crash(expected),
+                crash(actual)
+            ),
+        }
+    }
+}
+
+/// Runs a sequence of BrainfuckInstructions for at most `max_steps` steps and records the results.
+/// A panic in the Interpreter is caught and recorded as a crash.
+///
+/// # Arguments
+///
+/// * `code` - The sequence of BrainfuckInstructions to run.
+/// * `input` - The bytes the program reads from its input.
+/// * `max_steps` - The maximum number of instructions to execute.
+pub fn execute(code: Vec<BrainfuckInstruction>, input: &[u8], max_steps: u64) -> Execution {
+    let output = OutputBuffer::new();
+
+    let mut interp = Interpreter::new(code);
+    interp.set_input(Box::new(Cursor::new(input.to_vec())));
+    interp.set_output(Box::new(output.clone()));
+    interp.set_step_budget(Some(max_steps));
+    let crash = panic::catch_unwind(AssertUnwindSafe(|| interp.run())).err().map(panic_message);
+
+    Execution {
+        output: output.contents(),
+        tape: interp.tape().to_vec(),
+        data_pointer: interp.data_pointer(),
+        finished: crash.is_none() && interp.is_done(),
+        crash,
+    }
+}
+
+/// Extracts the message from the payload of a panic.
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => String::from("unknown panic"),
+        },
+    }
+}
+
+/// Compares two executions of the same program, returning the first difference between them.
+/// If either execution did not finish, only the output they have in common is compared.
+/// Executions that crashed are reported once their common output has been compared, as their state cannot be.
+///
+/// # Arguments
+///
+/// * `expected` - The reference execution.
+/// * `actual` - The execution being checked.
+pub fn compare(expected: &Execution, actual: &Execution) -> Result<(), Divergence> {
+    let finished = expected.finished && actual.finished;
+
+    let length = if finished {
+        expected.output.len().max(actual.output.len())
+    } else {
+        expected.output.len().min(actual.output.len())
+    };
+
+    for offset in 0..length {
+        let a = expected.output.get(offset).cloned();
+        let b = actual.output.get(offset).cloned();
+        if a != b {
+            return Err(Divergence::Output {
+                offset,
+                expected: a,
+                actual: b,
+            });
+        }
+    }
+
+    if expected.crash.is_some() || actual.crash.is_some() {
+        return Err(Divergence::Crash {
+            expected: expected.crash.clone(),
+            actual: actual.crash.clone(),
+        });
+    }
+    if !finished {
+        return Ok(());
+    }
+
+    for (address, (a, b)) in expected.tape.iter().zip(actual.tape.iter()).enumerate() {
+        if a != b {
+            return Err(Divergence::Tape {
+                address,
+                expected: *a,
+                actual: *b,
+            });
+        }
+    }
+
+    if expected.data_pointer != actual.data_pointer {
+        return Err(Divergence::DataPointer {
+            expected: expected.data_pointer,
+            actual: actual.data_pointer,
+        });
+    }
+
+    Ok(())
+}
+
+/// Runs some Brainfuck source code both unoptimized and optimized, and compares the results.
+///
+/// # Arguments
+///
+/// * `source` - The Brainfuck source code to check.
+/// * `input` - The bytes the program reads from its input.
+/// * `max_steps` - The maximum number of instructions to execute in each run.
+pub fn check(source: &str, input: &[u8], max_steps: u64) -> Result<(), Divergence> {
+    let code = parser::parse_str(source.to_string());
+
+    let expected = execute(code.clone(), input, max_steps);
+    let actual = execute(optimizer::optimize(code, 10), input, max_steps);
+
+    compare(&expected, &actual)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::Generator;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn compare_finds_output_divergence() {
+        let expected = execute(parser::parse_str(String::from("+.+.")), &[], 100);
+        let actual = execute(parser::parse_str(String::from("+.++.")), &[], 100);
+
+        assert_eq!(
+            compare(&expected, &actual),
+            Err(Divergence::Output {
+                offset: 1,
+                expected: Some(2),
+                actual: Some(3)
+            })
+        );
+    }
+
+    #[test]
+    fn compare_finds_missing_output() {
+        let expected = execute(parser::parse_str(String::from("+..")), &[], 100);
+        let actual = execute(parser::parse_str(String::from("+.")), &[], 100);
+
+        assert_eq!(
+            compare(&expected, &actual),
+            Err(Divergence::Output {
+                offset: 1,
+                expected: Some(1),
+                actual: None
+            })
+        );
+    }
+
+    #[test]
+    fn compare_finds_tape_divergence() {
+        let expected = execute(parser::parse_str(String::from(">+<")), &[], 100);
+        let actual = execute(parser::parse_str(String::from(">++<")), &[], 100);
+
+        assert_eq!(
+            compare(&expected, &actual),
+            Err(Divergence::Tape {
+                address: 1,
+                expected: 1,
+                actual: 2
+            })
+        );
+    }
+
+    #[test]
+    fn compare_finds_data_pointer_divergence() {
+        let expected = execute(parser::parse_str(String::from(">")), &[], 100);
+        let actual = execute(parser::parse_str(String::from(">>")), &[], 100);
+
+        assert_eq!(
+            compare(&expected, &actual),
+            Err(Divergence::DataPointer {
+                expected: 1,
+                actual: 2
+            })
+        );
+    }
+
+    #[test]
+    fn compare_only_checks_common_output_of_unfinished_executions() {
+        let expected = execute(parser::parse_str(String::from("+[.]")), &[], 10);
+        let actual = execute(parser::parse_str(String::from("+[.]")), &[], 100);
+
+        assert_eq!(compare(&expected, &actual), Ok(()));
+    }
+
+    #[test]
+    fn compare_reports_programs_that_leave_the_tape() {
+        let expected = execute(parser::parse_str(String::from("+.<+")), &[], 100);
+        let actual = execute(parser::parse_str(String::from("+.")), &[], 100);
+
+        assert_eq!(expected.output, vec![1]);
+        assert!(!expected.finished);
+        match compare(&expected, &actual) {
+            Err(Divergence::Crash {
+                expected: Some(_),
+                actual: None,
+            }) => {}
+            result => panic!("{:?}", result),
+        }
+        assert!(check("[<]<[<]", &[], 100).is_err());
+    }
+
+    #[test]
+    fn optimizer_preserves_behavior_of_test_programs() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("bf".as_ref()) {
+                continue;
+            }
+
+            let source = fs::read_to_string(&path).unwrap();
+            if let Err(divergence) = check(&source, b"7\n", 200_000) {
+                panic!("{}: {}", path.display(), divergence);
+            }
+        }
+    }
+
+    #[test]
+    fn optimizer_preserves_behavior_of_random_programs() {
+        let mut generator = Generator::new(0);
+
+        for _ in 0..500 {
+            let source = generator.generate();
+            if let Err(divergence) = check(&source, b"\x03\x01\x04\x01\x05", 1_000_000) {
+                panic!("{}\n{}", source, divergence);
+            }
+        }
+    }
+}
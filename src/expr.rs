@@ -0,0 +1,479 @@
+//! A small expression language for inspecting the state of an Interpreter.
+//!
+//! Expressions evaluate to signed integers, with comparisons and logical operators producing 1 for
+//! true and 0 for false. The following are supported, from lowest to highest precedence:
+//!
+//! * `a || b`, `a && b`
+//! * `a == b`, `a != b`, `a < b`, `a <= b`, `a > b`, `a >= b`
+//! * `a + b`, `a - b`
+//! * `a * b`, `a / b`, `a % b`
+//! * `-a`, `!a`, and `*a`, which reads the tape cell at address `a`
+//! * decimal numbers, hexadecimal numbers prefixed with `0x`, `dp`, `ip`, `steps`, `cell[a]` (the same as `*a`),
+//!   and parentheses
+
+use crate::interp::Interpreter;
+use std::fmt;
+
+/// The deepest nesting of parentheses, brackets and unary operators that `parse` accepts.
+/// The parser is recursive, so this keeps hostile expressions from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+/// UnaryOp represents an operator with a single operand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+/// BinaryOp represents an operator with two operands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 3,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 5,
+        }
+    }
+}
+
+/// Expr represents a parsed expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// Number is an integer literal.
+    Number(i64),
+    /// DataPointer is the current data pointer, written `dp`.
+    DataPointer,
+    /// InstructionPointer is the current instruction pointer, written `ip`.
+    InstructionPointer,
+    /// Steps is the number of instructions executed so far, written `steps`.
+    Steps,
+    /// Cell is the value of the tape cell at an address, written `*a` or `cell[a]`.
+    Cell(Box<Expr>),
+    /// Unary applies a UnaryOp to an operand.
+    Unary(UnaryOp, Box<Expr>),
+    /// Binary applies a BinaryOp to two operands.
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Evaluates the Expr against the current state of an Interpreter.
+    /// Fails if a tape cell outside of the tape is read, or if there is a division by zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `interp` - The Interpreter whose state is inspected.
+    pub fn evaluate(&self, interp: &Interpreter) -> Result<i64, String> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::DataPointer => Ok(interp.data_pointer() as i64),
+            Expr::InstructionPointer => Ok(interp.instruction_pointer() as i64),
+            Expr::Steps => Ok(interp.steps() as i64),
+            Expr::Cell(address) => {
+                let address = address.evaluate(interp)?;
+                if address < 0 {
+                    return Err(format!("Address out of bounds: {}", address));
+                }
+                interp.get(address as usize).map(|value| value as i64)
+            }
+            Expr::Unary(op, operand) => {
+                let operand = operand.evaluate(interp)?;
+                Ok(match op {
+                    UnaryOp::Negate => operand.wrapping_neg(),
+                    UnaryOp::Not => (operand == 0) as i64,
+                })
+            }
+            Expr::Binary(BinaryOp::And, a, b) => {
+                Ok((a.evaluate(interp)? != 0 && b.evaluate(interp)? != 0) as i64)
+            }
+            Expr::Binary(BinaryOp::Or, a, b) => {
+                Ok((a.evaluate(interp)? != 0 || b.evaluate(interp)? != 0) as i64)
+            }
+            Expr::Binary(op, a, b) => {
+                let a = a.evaluate(interp)?;
+                let b = b.evaluate(interp)?;
+                match op {
+                    BinaryOp::Add => Ok(a.wrapping_add(b)),
+                    BinaryOp::Sub => Ok(a.wrapping_sub(b)),
+                    BinaryOp::Mul => Ok(a.wrapping_mul(b)),
+                    BinaryOp::Div | BinaryOp::Rem if b == 0 => Err(String::from("Division by zero")),
+                    BinaryOp::Div => Ok(a.wrapping_div(b)),
+                    BinaryOp::Rem => Ok(a.wrapping_rem(b)),
+                    BinaryOp::Eq => Ok((a == b) as i64),
+                    BinaryOp::Ne => Ok((a != b) as i64),
+                    BinaryOp::Lt => Ok((a < b) as i64),
+                    BinaryOp::Le => Ok((a <= b) as i64),
+                    BinaryOp::Gt => Ok((a > b) as i64),
+                    BinaryOp::Ge => Ok((a >= b) as i64),
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// Writes the Expr, adding parentheses if it binds less tightly than `precedence`.
+    fn write(&self, f: &mut fmt::Formatter, precedence: u8) -> fmt::Result {
+        match self {
+            Expr::Number(value) if *value < 0 && precedence > 5 => write!(f, "({})", value),
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::DataPointer => write!(f, "dp"),
+            Expr::InstructionPointer => write!(f, "ip"),
+            Expr::Steps => write!(f, "steps"),
+            Expr::Cell(address) => {
+                write!(f, "*")?;
+                address.write(f, 6)
+            }
+            Expr::Unary(op, operand) => {
+                write!(f, "{}", if *op == UnaryOp::Negate { "-" } else { "!" })?;
+                operand.write(f, 6)
+            }
+            Expr::Binary(op, a, b) => {
+                let own = op.precedence();
+                if own < precedence {
+                    write!(f, "(")?;
+                }
+                // Operators are left-associative, so a right operand of the same precedence needs parentheses.
+                a.write(f, own)?;
+                write!(f, " {} ", op.symbol())?;
+                b.write(f, own + 1)?;
+                if own < precedence {
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    /// Formats the Expr so that `parse` produces the same Expr again.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// Parses an Expr from a string.
+///
+/// # Arguments
+///
+/// * `source` - The expression to parse.
+pub fn parse(source: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        position: 0,
+        depth: 0,
+    };
+    let result = parser.or()?;
+
+    match parser.peek() {
+        None => Ok(result),
+        Some(token) => Err(format!("Unexpected token: {}", token)),
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut result = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            result.push(chars[start..i].iter().collect());
+        } else {
+            let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            if ["==", "!=", "<=", ">=", "&&", "||"].contains(&pair.as_str()) {
+                result.push(pair);
+                i += 2;
+            } else if "+-*/%<>!()[]".contains(c) {
+                result.push(c.to_string());
+                i += 1;
+            } else {
+                return Err(format!("Unexpected character: {}", c));
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|token| token.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            }
+            None => Err(String::from("Unexpected end of expression")),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            Err(format!("Expected {}, found {}", expected, token))
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operators: &[(&str, BinaryOp)],
+        operand: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut result = operand(self)?;
+
+        while let Some(&(_, op)) = operators.iter().find(|(token, _)| self.peek() == Some(*token)) {
+            self.position += 1;
+            result = Expr::Binary(op, Box::new(result), Box::new(operand(self)?));
+        }
+
+        Ok(result)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[("||", BinaryOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[("&&", BinaryOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[
+                ("==", BinaryOp::Eq),
+                ("!=", BinaryOp::Ne),
+                ("<=", BinaryOp::Le),
+                (">=", BinaryOp::Ge),
+                ("<", BinaryOp::Lt),
+                (">", BinaryOp::Gt),
+            ],
+            Self::sum,
+        )
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(&[("+", BinaryOp::Add), ("-", BinaryOp::Sub)], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        // Every recursive path through the grammar passes through here.
+        if self.depth == MAX_DEPTH {
+            return Err(String::from("Expression is nested too deeply"));
+        }
+        self.depth += 1;
+        let result = self.nested_unary();
+        self.depth -= 1;
+        result
+    }
+
+    fn nested_unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some("-") => {
+                self.position += 1;
+                Ok(Expr::Unary(UnaryOp::Negate, Box::new(self.unary()?)))
+            }
+            Some("!") => {
+                self.position += 1;
+                Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?)))
+            }
+            Some("*") => {
+                self.position += 1;
+                Ok(Expr::Cell(Box::new(self.unary()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.next()?;
+
+        match token.as_str() {
+            "dp" => Ok(Expr::DataPointer),
+            "ip" => Ok(Expr::InstructionPointer),
+            "steps" => Ok(Expr::Steps),
+            "cell" => {
+                self.expect("[")?;
+                let address = self.or()?;
+                self.expect("]")?;
+                Ok(Expr::Cell(Box::new(address)))
+            }
+            "(" => {
+                let result = self.or()?;
+                self.expect(")")?;
+                Ok(result)
+            }
+            _ => match token.strip_prefix("0x") {
+                Some(digits) => i64::from_str_radix(digits, 16),
+                None => token.parse::<i64>(),
+            }
+            .map(Expr::Number)
+            .map_err(|_| format!("Unexpected token: {}", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn evaluate(source: &str, interp: &Interpreter) -> Result<i64, String> {
+        parse(source)?.evaluate(interp)
+    }
+
+    #[test]
+    fn parse_respects_precedence() {
+        assert_eq!(
+            parse("1 + 2 * dp == 3 || !ip"),
+            Ok(Expr::Binary(
+                BinaryOp::Or,
+                Box::new(Expr::Binary(
+                    BinaryOp::Eq,
+                    Box::new(Expr::Binary(
+                        BinaryOp::Add,
+                        Box::new(Expr::Number(1)),
+                        Box::new(Expr::Binary(
+                            BinaryOp::Mul,
+                            Box::new(Expr::Number(2)),
+                            Box::new(Expr::DataPointer)
+                        ))
+                    )),
+                    Box::new(Expr::Number(3))
+                )),
+                Box::new(Expr::Unary(UnaryOp::Not, Box::new(Expr::InstructionPointer)))
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_reads_hex_numbers() {
+        assert_eq!(parse("0x1F"), Ok(Expr::Number(31)));
+        assert_eq!(parse("0xg"), Err(String::from("Unexpected token: 0xg")));
+    }
+
+    #[test]
+    fn parse_reports_errors() {
+        assert_eq!(parse("1 +"), Err(String::from("Unexpected end of expression")));
+        assert_eq!(parse("(1"), Err(String::from("Unexpected end of expression")));
+        assert_eq!(parse("1 2"), Err(String::from("Unexpected token: 2")));
+        assert_eq!(parse("cell(1)"), Err(String::from("Expected [, found (")));
+        assert_eq!(parse("1 $ 2"), Err(String::from("Unexpected character: $")));
+        assert_eq!(
+            parse(&format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000))),
+            Err(String::from("Expression is nested too deeply"))
+        );
+        assert_eq!(parse(&format!("{}1", "-".repeat(100_000))), Err(String::from("Expression is nested too deeply")));
+        assert!(parse(&format!("{}1{}", "(".repeat(MAX_DEPTH - 1), ")".repeat(MAX_DEPTH - 1))).is_ok());
+    }
+
+    #[test]
+    fn evaluate_reads_interpreter_state() {
+        let code = parser::parse_str(String::from("+++>++"));
+        let mut interp = Interpreter::new(code);
+        interp.run();
+
+        assert_eq!(evaluate("dp", &interp), Ok(1));
+        assert_eq!(evaluate("ip", &interp), Ok(6));
+        assert_eq!(evaluate("steps", &interp), Ok(6));
+        assert_eq!(evaluate("*dp", &interp), Ok(2));
+        assert_eq!(evaluate("cell[dp - 1] * 10 + *1", &interp), Ok(32));
+        assert_eq!(evaluate("*dp == 2 && dp > 0", &interp), Ok(1));
+        assert_eq!(evaluate("*(dp + 1) != 0", &interp), Ok(0));
+        assert_eq!(evaluate("-7 / 2", &interp), Ok(-3));
+        assert_eq!(evaluate("-7 % 2", &interp), Ok(-1));
+    }
+
+    #[test]
+    fn evaluate_reports_errors() {
+        let code = parser::parse_str(String::from("+"));
+        let interp = Interpreter::new(code);
+
+        assert_eq!(evaluate("1 / 0", &interp), Err(String::from("Division by zero")));
+        assert_eq!(evaluate("*-1", &interp), Err(String::from("Address out of bounds: -1")));
+        assert_eq!(evaluate("*100000000", &interp), Err(String::from("Address out of bounds: 100000000")));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for source in [
+            "*dp == 0",
+            "dp > 50 && (ip == 3 || steps % 2 != 0)",
+            "1 - (2 - 3) - 4",
+            "-(1 + 2) * *(dp + 1)",
+            "!!cell[dp - 1]",
+            "2 * -3",
+        ] {
+            let expr = parse(source).unwrap();
+
+            assert_eq!(parse(&expr.to_string()), Ok(expr.clone()), "{}", expr);
+        }
+
+        assert_eq!(parse("((*dp) == (0))").unwrap().to_string(), "*dp == 0");
+        assert_eq!(parse("1 - (2 - 3)").unwrap().to_string(), "1 - (2 - 3)");
+        assert_eq!(parse("cell[dp + 1]").unwrap().to_string(), "*(dp + 1)");
+    }
+}
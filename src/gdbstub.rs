@@ -0,0 +1,456 @@
+//! A stub for the GDB remote serial protocol, so that `gdb` can attach to an Interpreter.
+//!
+//! gdb has no Brainfuck architecture and ignores target descriptions it cannot match to one of its own, so the stub
+//! sends none and lays out its registers like gdb's default x86-64 ones instead: the data pointer is `rax` (register
+//! 0) and the instruction pointer is `rip` (register 16, also `$pc`). The other registers read as zero. A gdb for
+//! another host needs `set architecture i386:x86-64` before `target remote`.
+//! Memory is the tape, so `x/16xb 0` shows the first 16 cells, while breakpoints are set on code addresses with
+//! `break *<address>`. Watchpoints map to the Interpreter's own watchpoints. Output written by the program is
+//! forwarded to gdb's console.
+
+use crate::interp::{Interpreter, OutputBuffer, StopReason, WatchKind, Watchpoint};
+use std::convert::TryInto;
+use std::io::{self, BufReader, Read, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+/// The number of instructions executed between checks for an interrupt while the program is running.
+const RUN_CHUNK: u64 = 100_000;
+
+/// The x86-64 register that holds the data pointer, `rax`.
+const DATA_POINTER_REGISTER: usize = 0;
+
+/// The x86-64 register that holds the instruction pointer, `rip`.
+const INSTRUCTION_POINTER_REGISTER: usize = 16;
+
+/// Something received from gdb.
+#[derive(Debug, Clone, PartialEq)]
+enum Incoming {
+    /// A packet whose checksum matched.
+    Packet(String),
+    /// A packet whose checksum did not match, which gdb will send again.
+    Corrupt,
+    /// A request to stop the running program, sent when the user presses Ctrl-C.
+    Interrupt,
+}
+
+/// Reads the next packet or interrupt, skipping acknowledgements. Returns `None` at the end of the stream.
+fn read_incoming(reader: &mut impl Read) -> io::Result<Option<Incoming>> {
+    let mut byte = [0];
+
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        match byte[0] {
+            0x03 => return Ok(Some(Incoming::Interrupt)),
+            b'$' => break,
+            _ => {}
+        }
+    }
+
+    let mut data = Vec::new();
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] == b'#' {
+            break;
+        }
+        data.push(byte[0]);
+    }
+
+    let mut checksum = [0; 2];
+    reader.read_exact(&mut checksum)?;
+    let expected = std::str::from_utf8(&checksum)
+        .ok()
+        .and_then(|c| u8::from_str_radix(c, 16).ok());
+
+    if expected == Some(self::checksum(&data)) {
+        Ok(Some(Incoming::Packet(String::from_utf8_lossy(&data).into_owned())))
+    } else {
+        Ok(Some(Incoming::Corrupt))
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| text.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+/// Parses a hexadecimal number, as used for addresses and lengths.
+fn number(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+/// Serves gdb until it detaches, kills the program or disconnects.
+///
+/// # Arguments
+///
+/// * `interp` - The Interpreter to debug.
+/// * `output` - The buffer the Interpreter writes its output to, which is forwarded to gdb.
+/// * `reader` - Where packets from gdb are read from.
+/// * `writer` - Where packets to gdb are written.
+pub fn serve(
+    interp: &mut Interpreter,
+    output: &OutputBuffer,
+    reader: impl Read + Send + 'static,
+    writer: impl Write,
+) -> io::Result<()> {
+    // Packets are read on their own thread so that an interrupt can arrive while the program is running.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        while let Ok(Some(incoming)) = read_incoming(&mut reader) {
+            if sender.send(incoming).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut stub = Stub {
+        interp,
+        output,
+        writer,
+        acknowledge: true,
+        running: false,
+        finished: false,
+    };
+    while !stub.finished {
+        let incoming = if stub.running {
+            match receiver.try_recv() {
+                Ok(incoming) => Some(incoming),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match receiver.recv() {
+                Ok(incoming) => Some(incoming),
+                Err(_) => break,
+            }
+        };
+
+        match incoming {
+            Some(Incoming::Packet(packet)) => {
+                if stub.acknowledge {
+                    stub.writer.write_all(b"+")?;
+                }
+                stub.handle(&packet)?;
+            }
+            Some(Incoming::Corrupt) => stub.writer.write_all(b"-")?,
+            Some(Incoming::Interrupt) if stub.running => {
+                stub.running = false;
+                stub.send("S02")?;
+            }
+            _ => {}
+        }
+        stub.tick()?;
+        stub.writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// The state of a connection to gdb.
+struct Stub<'a, W: Write> {
+    interp: &'a mut Interpreter,
+    output: &'a OutputBuffer,
+    writer: W,
+    acknowledge: bool,
+    running: bool,
+    finished: bool,
+}
+
+impl<W: Write> Stub<'_, W> {
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        write!(self.writer, "${}#{:02x}", data, checksum(data.as_bytes()))
+    }
+
+    fn handle(&mut self, packet: &str) -> io::Result<()> {
+        let reply = match packet {
+            "?" => String::from("S05"),
+            "g" => hex(&self.registers()),
+            "c" => {
+                self.running = true;
+                return Ok(());
+            }
+            "s" => {
+                let reason = self.interp.step_many(1);
+                return self.report(reason);
+            }
+            "k" => {
+                self.finished = true;
+                return Ok(());
+            }
+            "D" => {
+                self.finished = true;
+                String::from("OK")
+            }
+            "QStartNoAckMode" => {
+                self.send("OK")?;
+                self.acknowledge = false;
+                return Ok(());
+            }
+            "qC" => String::from("QC1"),
+            "qAttached" => String::from("1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ if packet.starts_with("qSupported") => {
+                String::from("PacketSize=4000;QStartNoAckMode+")
+            }
+            _ if packet.starts_with('H') || packet.starts_with('T') => String::from("OK"),
+            _ => self.handle_with_arguments(packet).unwrap_or_else(|| String::from("E01")),
+        };
+
+        self.send(&reply)
+    }
+
+    /// Handles packets that take arguments, returning `None` if the arguments are invalid.
+    /// Packets that are not supported get an empty reply, as the protocol requires.
+    fn handle_with_arguments(&mut self, packet: &str) -> Option<String> {
+        if !packet.is_char_boundary(1) {
+            return Some(String::new());
+        }
+        let (command, arguments) = packet.split_at(1);
+
+        match command {
+            "p" => {
+                // The x86-64 registers after the ones in `registers` are not available, which an empty reply tells gdb.
+                let start = number(arguments)?.checked_mul(8)?;
+                Some(self.registers().get(start..start.checked_add(8)?).map_or(String::new(), hex))
+            }
+            "P" => {
+                let (register, value) = arguments.split_once('=')?;
+                let value = unhex(value)?;
+                let value = u64::from_le_bytes(value.as_slice().try_into().ok()?) as usize;
+                let result = match number(register)? {
+                    INSTRUCTION_POINTER_REGISTER => self.interp.jump(value),
+                    DATA_POINTER_REGISTER => self.interp.select(value),
+                    _ => return None,
+                };
+                result.ok().map(|_| String::from("OK"))
+            }
+            "m" => {
+                let (address, length) = arguments.split_once(',')?;
+                let (address, length) = (number(address)?, number(length)?);
+                self.interp.tape().get(address..address.checked_add(length)?).map(hex)
+            }
+            "M" => {
+                let (range, data) = arguments.split_once(':')?;
+                let (address, length) = range.split_once(',')?;
+                let (address, data) = (number(address)?, unhex(data)?);
+                if data.len() != number(length)? || address.checked_add(data.len())? > self.interp.tape().len() {
+                    return None;
+                }
+                for (offset, value) in data.iter().enumerate() {
+                    self.interp.set(address + offset, *value).ok()?;
+                }
+                Some(String::from("OK"))
+            }
+            "Z" | "z" => {
+                let mut fields = arguments.split(',');
+                let kind = fields.next()?;
+                let address = number(fields.next()?)?;
+                let insert = command == "Z";
+
+                let kinds = match kind {
+                    "0" | "1" => {
+                        if insert {
+                            self.interp.set_breakpoint(address).ok()?;
+                        } else {
+                            self.interp.delete_breakpoint(address);
+                        }
+                        return Some(String::from("OK"));
+                    }
+                    "2" => vec![WatchKind::Write],
+                    "3" => vec![WatchKind::Read],
+                    "4" => vec![WatchKind::Read, WatchKind::Write],
+                    _ => return Some(String::new()),
+                };
+                for kind in kinds {
+                    if insert {
+                        self.interp.set_watchpoint(address, kind).ok()?;
+                    } else {
+                        self.interp.delete_watchpoint(address, Some(kind));
+                    }
+                }
+                Some(String::from("OK"))
+            }
+            _ => Some(String::new()),
+        }
+    }
+
+    /// Returns the general purpose registers of x86-64 up to `rip`, in gdb's order and little-endian layout.
+    fn registers(&self) -> Vec<u8> {
+        let mut registers = [0u64; INSTRUCTION_POINTER_REGISTER + 1];
+        registers[DATA_POINTER_REGISTER] = self.interp.data_pointer() as u64;
+        registers[INSTRUCTION_POINTER_REGISTER] = self.interp.instruction_pointer() as u64;
+        registers.iter().flat_map(|register| register.to_le_bytes()).collect()
+    }
+
+    /// Runs the next chunk of the program if it is running.
+    fn tick(&mut self) -> io::Result<()> {
+        if self.running {
+            let reason = self.interp.step_many(RUN_CHUNK);
+            if reason != StopReason::Stepped {
+                self.running = false;
+                self.report(reason)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends any output written by the program, followed by a stop reply.
+    fn report(&mut self, reason: StopReason) -> io::Result<()> {
+        let output = self.output.take();
+        if !output.is_empty() {
+            self.send(&format!("O{}", hex(&output)))?;
+        }
+
+        match reason {
+            StopReason::Watchpoint(Watchpoint { address, kind }) => {
+                let name = if kind == WatchKind::Read { "rwatch" } else { "watch" };
+                self.send(&format!("T05{}:{:x};", name, address))
+            }
+            StopReason::Done => {
+                self.finished = true;
+                self.send("W00")
+            }
+            StopReason::Interrupted => self.send("S02"),
+            _ => self.send("S05"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use std::io::Cursor;
+
+    fn packet(data: &str) -> Vec<u8> {
+        format!("${}#{:02x}", data, checksum(data.as_bytes())).into_bytes()
+    }
+
+    /// Sends scripted packets to a stub debugging `source` and returns the packets it replied with.
+    fn session(source: &str, packets: &[&str]) -> (Vec<String>, Interpreter) {
+        let output = OutputBuffer::new();
+        let mut interp = Interpreter::new(parser::parse_str(String::from(source)));
+        interp.set_output(Box::new(output.clone()));
+
+        let mut input = Vec::new();
+        for data in packets {
+            if *data == "\x03" {
+                input.push(0x03);
+            } else {
+                input.extend(packet(data));
+                input.push(b'+');
+            }
+        }
+        let mut written = Vec::new();
+        serve(&mut interp, &output, Cursor::new(input), &mut written).unwrap();
+
+        let mut reader = Cursor::new(written);
+        let mut replies = Vec::new();
+        while let Some(incoming) = read_incoming(&mut reader).unwrap() {
+            match incoming {
+                Incoming::Packet(reply) => replies.push(reply),
+                other => panic!("Unexpected {:?}", other),
+            }
+        }
+        (replies, interp)
+    }
+
+    #[test]
+    fn read_incoming_checks_checksums() {
+        let mut reader = Cursor::new(b"+$g#67\x03$g#00".to_vec());
+
+        assert_eq!(read_incoming(&mut reader).unwrap(), Some(Incoming::Packet(String::from("g"))));
+        assert_eq!(read_incoming(&mut reader).unwrap(), Some(Incoming::Interrupt));
+        assert_eq!(read_incoming(&mut reader).unwrap(), Some(Incoming::Corrupt));
+        assert_eq!(read_incoming(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn registers_and_memory_map_to_the_interpreter() {
+        let (replies, interp) = session(
+            "+++>++",
+            &[
+                "qSupported:swbreak+",
+                "?",
+                "s",
+                "s",
+                "g",
+                "p0",
+                "p11",
+                "P0=0200000000000000",
+                "m0,3",
+                "M0,2:0a0b",
+                "m0,2",
+                "m2,ffff",
+            ],
+        );
+
+        assert_eq!(
+            replies,
+            vec![
+                "PacketSize=4000;QStartNoAckMode+",
+                "S05",
+                "S05",
+                "S05",
+                &format!("{}{}", "0".repeat(256), "0200000000000000"),
+                "0000000000000000",
+                "",
+                "OK",
+                "020000",
+                "OK",
+                "0a0b",
+                "E01",
+            ]
+        );
+        assert_eq!(interp.data_pointer(), 2);
+    }
+
+    #[test]
+    fn out_of_range_registers_and_addresses_are_rejected() {
+        let (replies, _) = session(
+            "+",
+            &["pffffffffffffffff", "p2000000000000000", "Mffffffffffffffff,1:00", "mffffffffffffffff,1"],
+        );
+
+        assert_eq!(replies, vec!["E01", "E01", "E01", "E01"]);
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints_stop_the_program() {
+        let (replies, interp) = session(
+            "++[>+.<-]>.",
+            &["Z0,5,1", "c", "z0,5,1", "Z2,1,1", "c", "z2,1,1", "c"],
+        );
+
+        assert_eq!(
+            replies,
+            vec!["OK", "S05", "OK", "OK", "O01", "T05watch:1;", "OK", "O0202", "W00"]
+        );
+        assert_eq!(interp.tape()[1], 2);
+    }
+
+    #[test]
+    fn interrupt_stops_a_running_program() {
+        let (replies, interp) = session("+[]", &["QStartNoAckMode", "c", "\x03", "p0", "D"]);
+
+        assert_eq!(replies, vec!["OK", "S02", "0000000000000000", "OK"]);
+        assert!(interp.steps() > 0);
+    }
+}
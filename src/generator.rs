@@ -0,0 +1,242 @@
+//! A generator of random Brainfuck programs.
+
+/// Config controls the shape of the programs produced by a Generator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// The approximate number of instructions in each program, not counting loop setup.
+    pub length: usize,
+    /// The maximum nesting depth of loops.
+    pub max_depth: usize,
+    /// The number of tape cells programs are allowed to touch.
+    pub tape_width: usize,
+    /// The maximum number of times a single loop may iterate.
+    pub max_iterations: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            length: 100,
+            max_depth: 3,
+            tape_width: 16,
+            max_iterations: 5,
+        }
+    }
+}
+
+/// A deterministic generator of random Brainfuck programs.
+///
+/// Generated programs always terminate. Every loop starts by setting a counter cell to a small
+/// value, and its body decrements the counter exactly once per iteration without otherwise
+/// touching it, returning the data pointer to the counter before the `]`. Other cells are cleared
+/// only with loops like `[-]` or `[+++]`, which terminate from any starting value. The data pointer
+/// never leaves the first `tape_width` cells.
+pub struct Generator {
+    state: u64,
+    config: Config,
+}
+
+impl Generator {
+    /// Creates a new Generator with the default Config.
+    /// The same seed always produces the same programs.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed for the pseudo-random number generator.
+    pub fn new(seed: u64) -> Self {
+        Self::with_config(seed, Config::default())
+    }
+
+    /// Creates a new Generator with some Config.
+    /// The same seed and Config always produce the same programs.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed for the pseudo-random number generator.
+    /// * `config` - The Config controlling the shape of generated programs.
+    pub fn with_config(seed: u64, config: Config) -> Self {
+        assert!(config.tape_width > 0, "tape_width must be at least 1");
+
+        Self {
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+            config,
+        }
+    }
+
+    /// Generates a random Brainfuck program.
+    pub fn generate(&mut self) -> String {
+        let mut result = String::new();
+        let mut position = 0;
+        let mut counters = Vec::new();
+        self.block(&mut result, self.config.length, &mut position, &mut counters);
+        result
+    }
+
+    fn block(&mut self, result: &mut String, length: usize, position: &mut usize, counters: &mut Vec<usize>) {
+        let mut generated = 0;
+
+        while generated < length {
+            generated += 1;
+
+            let protected = counters.contains(position);
+            match self.below(12) {
+                0 | 1 if !protected => result.push('+'),
+                2 | 3 if !protected => result.push('-'),
+                4 if *position + 1 < self.config.tape_width => {
+                    result.push('>');
+                    *position += 1;
+                }
+                5 if *position > 0 => {
+                    result.push('<');
+                    *position -= 1;
+                }
+                6 => result.push('.'),
+                7 if !protected => result.push(','),
+                8 if !protected => {
+                    result.push('[');
+                    for _ in 0..(self.below(2) * 2 + 1) {
+                        result.push(if self.below(2) == 0 { '-' } else { '+' });
+                    }
+                    result.push(']');
+                }
+                9..=11 if !protected && counters.len() < self.config.max_depth => {
+                    let counter = *position;
+                    let iterations = self.below(self.config.max_iterations as u64) + 1;
+                    let body_length = self.below(length as u64 / 2 + 1) as usize;
+
+                    result.push_str("[-]");
+                    for _ in 0..iterations {
+                        result.push('+');
+                    }
+
+                    result.push('[');
+                    counters.push(counter);
+                    self.block(result, body_length, position, counters);
+                    counters.pop();
+                    move_to(result, position, counter);
+                    result.push_str("-]");
+
+                    generated += body_length;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+}
+
+fn move_to(result: &mut String, position: &mut usize, target: usize) {
+    while *position < target {
+        result.push('>');
+        *position += 1;
+    }
+    while *position > target {
+        result.push('<');
+        *position -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difftest;
+    use crate::parser;
+
+    #[test]
+    fn generate_is_deterministic() {
+        let a = Generator::new(42).generate();
+        let b = Generator::new(42).generate();
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn generate_balances_loops() {
+        let mut subject = Generator::new(7);
+
+        for _ in 0..100 {
+            let program = subject.generate();
+
+            let mut level = 0;
+            let mut positions = vec![0isize];
+            for c in program.chars() {
+                let position = positions.last_mut().unwrap();
+                match c {
+                    '>' => *position += 1,
+                    '<' => *position -= 1,
+                    '[' => {
+                        level += 1;
+                        let current = *position;
+                        positions.push(current);
+                    }
+                    ']' => {
+                        level -= 1;
+                        let end = positions.pop().unwrap();
+                        assert_eq!(Some(&end), positions.last());
+                    }
+                    _ => {}
+                }
+                assert!(*positions.last().unwrap() >= 0);
+            }
+            assert_eq!(level, 0);
+        }
+    }
+
+    #[test]
+    fn generate_respects_config() {
+        let config = Config {
+            length: 40,
+            max_depth: 1,
+            tape_width: 3,
+            max_iterations: 2,
+        };
+        let mut subject = Generator::with_config(3, config);
+
+        for _ in 0..100 {
+            let program = subject.generate();
+
+            let mut position = 0isize;
+            let mut max_position = 0;
+            let mut level = 0;
+            let mut max_level = 0;
+            for c in program.chars() {
+                match c {
+                    '>' => position += 1,
+                    '<' => position -= 1,
+                    '[' => level += 1,
+                    ']' => level -= 1,
+                    _ => {}
+                }
+                max_position = max_position.max(position);
+                max_level = max_level.max(level);
+            }
+
+            assert!(max_position < 3);
+            // Clear loops inside of counted loops add an extra level of nesting.
+            assert!(max_level <= 2);
+        }
+    }
+
+    #[test]
+    fn generate_terminates() {
+        let mut subject = Generator::new(11);
+
+        for _ in 0..100 {
+            let program = subject.generate();
+
+            let execution = difftest::execute(parser::parse_str(program.clone()), b"\x01\x02\x03", 10_000_000);
+
+            assert!(execution.finished, "{}", program);
+        }
+    }
+}
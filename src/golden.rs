@@ -0,0 +1,387 @@
+//! Golden input/output tests for Brainfuck programs.
+//!
+//! A golden test is a Brainfuck source file `name.bf` paired with the output it is expected to
+//! produce. `name.out` holds the complete output of a program that terminates, while `name.head`
+//! holds the beginning of the output of a program that runs forever. If `name.in` exists, its
+//! contents are given to the program as input.
+
+use crate::compiler;
+use crate::interp::{Interpreter, OutputBuffer};
+use crate::optimizer;
+use crate::parser;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// The number of lines of context shown around the first difference in a diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// The maximum number of differing lines shown in a diff.
+const DIFF_LINES: usize = 10;
+
+/// A single golden test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    /// The name of the test, taken from the name of the source file.
+    pub name: String,
+    /// The path to the Brainfuck source file.
+    pub path: PathBuf,
+    /// The bytes given to the program as input.
+    pub input: Vec<u8>,
+    /// The output the program is expected to produce.
+    pub expected: Vec<u8>,
+    /// Whether `expected` is the complete output of the program, rather than just its beginning.
+    pub complete: bool,
+}
+
+/// Backend represents a way of executing a Brainfuck program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// Interpreter runs the optimized program with `interp::Interpreter`.
+    Interpreter,
+    /// C compiles the optimized program with `compiler::compile` and a C compiler, then runs it.
+    C,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Backend::Interpreter => write!(f, "interpreter"),
+            Backend::C => write!(f, "c"),
+        }
+    }
+}
+
+/// Options controls how golden tests are executed.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The maximum number of instructions the Interpreter backend may execute.
+    pub max_steps: u64,
+    /// The C compiler used by the C backend.
+    pub compiler: String,
+    /// The maximum amount of time a compiled program may run for.
+    pub timeout: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_steps: 10_000_000_000,
+            compiler: String::from("cc"),
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Finds all golden tests in a directory, sorted by name.
+/// Source files without a matching `.out` or `.head` file are ignored.
+///
+/// # Arguments
+///
+/// * `directory` - The directory to search.
+pub fn discover(directory: &Path) -> io::Result<Vec<Case>> {
+    let mut result = Vec::new();
+
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension() != Some("bf".as_ref()) {
+            continue;
+        }
+
+        let (expected, complete) = if path.with_extension("out").exists() {
+            (fs::read(path.with_extension("out"))?, true)
+        } else if path.with_extension("head").exists() {
+            (fs::read(path.with_extension("head"))?, false)
+        } else {
+            continue;
+        };
+
+        let input = if path.with_extension("in").exists() {
+            fs::read(path.with_extension("in"))?
+        } else {
+            Vec::new()
+        };
+
+        result.push(Case {
+            name: path.file_stem().unwrap().to_string_lossy().into_owned(),
+            path,
+            input,
+            expected,
+            complete,
+        });
+    }
+
+    result.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(result)
+}
+
+/// Runs a golden test with some backend and compares its output to the expected output.
+/// Returns a description of the mismatch, including a diff, if the output is wrong.
+///
+/// # Arguments
+///
+/// * `case` - The golden test to run.
+/// * `backend` - The backend to run it with.
+/// * `options` - Options controlling execution.
+pub fn check(case: &Case, backend: Backend, options: &Options) -> Result<(), String> {
+    let output = match backend {
+        Backend::Interpreter => run_interpreter(case, options),
+        Backend::C => run_c(case, options),
+    }
+    .map_err(|e| e.to_string())?;
+
+    if output == case.expected {
+        Ok(())
+    } else {
+        Err(diff(&case.expected, &output))
+    }
+}
+
+/// Runs a golden test with the Interpreter backend and returns its output.
+/// For incomplete tests, execution stops once enough output has been produced.
+///
+/// # Arguments
+///
+/// * `case` - The golden test to run.
+/// * `options` - Options controlling execution.
+pub fn run_interpreter(case: &Case, options: &Options) -> io::Result<Vec<u8>> {
+    let source = fs::read_to_string(&case.path)?;
+    let code = optimizer::optimize(parser::parse_str(source), 10);
+    let output = OutputBuffer::new();
+
+    let mut interp = Interpreter::new(code);
+    interp.set_input(Box::new(Cursor::new(case.input.clone())));
+    interp.set_output(Box::new(output.clone()));
+
+    let mut steps = 0;
+    while !interp.is_done() {
+        if !case.complete && output.len() >= case.expected.len() {
+            break;
+        }
+        if steps >= options.max_steps {
+            return Err(io::Error::other(format!(
+                "Program did not finish within {} steps",
+                options.max_steps
+            )));
+        }
+
+        interp.step();
+        steps += 1;
+    }
+
+    Ok(limit(output.contents(), case))
+}
+
+/// Runs a golden test with the C backend and returns its output.
+/// For incomplete tests, the program is killed once enough output has been produced.
+///
+/// # Arguments
+///
+/// * `case` - The golden test to run.
+/// * `options` - Options controlling execution.
+pub fn run_c(case: &Case, options: &Options) -> io::Result<Vec<u8>> {
+    // Every call gets its own directory, since cases may be run from several threads at once.
+    static DIRECTORIES: AtomicUsize = AtomicUsize::new(0);
+    let directory = env::temp_dir().join(format!(
+        "bfkit-golden-{}-{}",
+        process::id(),
+        DIRECTORIES.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&directory)?;
+
+    let result = compile_and_run(case, options, &directory);
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+fn compile_and_run(case: &Case, options: &Options, directory: &Path) -> io::Result<Vec<u8>> {
+    let source = fs::read_to_string(&case.path)?;
+    let code = optimizer::optimize(parser::parse_str(source), 10);
+
+    let c_path = directory.join(format!("{}.c", case.name));
+    let executable = directory.join(&case.name);
+    fs::write(&c_path, compiler::compile(code))?;
+
+    let compilation = Command::new(&options.compiler)
+        .arg("-O1")
+        .arg("-w")
+        .arg("-o")
+        .arg(&executable)
+        .arg(&c_path)
+        .output()?;
+    if !compilation.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed:\n{}",
+            options.compiler,
+            String::from_utf8_lossy(&compilation.stderr)
+        )));
+    }
+
+    let mut child = Command::new(&executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let input = case.input.clone();
+    thread::spawn(move || {
+        // The program may exit without reading all of its input, so errors are expected here.
+        let _ = stdin.write_all(&input);
+    });
+
+    let mut stdout = child.stdout.take().unwrap();
+    let wanted = if case.complete {
+        None
+    } else {
+        Some(case.expected.len())
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            match stdout.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => output.extend_from_slice(&buffer[..n]),
+            }
+            if wanted.is_some_and(|wanted| output.len() >= wanted) {
+                break;
+            }
+        }
+        let _ = sender.send(output);
+    });
+
+    let result = receiver.recv_timeout(options.timeout);
+    let _ = child.kill();
+    let status = child.wait()?;
+
+    let output = result.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("Program did not finish within {:?}", options.timeout),
+        )
+    })?;
+
+    if case.complete && !status.success() {
+        return Err(io::Error::other(format!("Program exited with {}", status)));
+    }
+
+    Ok(limit(output, case))
+}
+
+fn limit(mut output: Vec<u8>, case: &Case) -> Vec<u8> {
+    if !case.complete {
+        output.truncate(case.expected.len());
+    }
+    output
+}
+
+/// Produces a line-based diff between the expected and actual output of a program, showing the
+/// lines between the first and last difference along with a few lines of context.
+///
+/// # Arguments
+///
+/// * `expected` - The expected output.
+/// * `actual` - The actual output.
+pub fn diff(expected: &[u8], actual: &[u8]) -> String {
+    let expected_text = String::from_utf8_lossy(expected);
+    let actual_text = String::from_utf8_lossy(actual);
+    let expected_lines: Vec<&str> = expected_text.split_inclusive('\n').collect();
+    let actual_lines: Vec<&str> = actual_text.split_inclusive('\n').collect();
+
+    let first = expected_lines
+        .iter()
+        .zip(actual_lines.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let last = expected_lines[first..]
+        .iter()
+        .rev()
+        .zip(actual_lines[first..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let expected_end = expected_lines.len() - last;
+    let actual_end = actual_lines.len() - last;
+
+    let mut result = format!(
+        "Output differs at line {} (expected {} bytes, got {} bytes)\n",
+        first + 1,
+        expected.len(),
+        actual.len()
+    );
+
+    let line = |result: &mut String, prefix: char, text: &str| {
+        result.push(prefix);
+        result.push_str(&text.escape_debug().to_string());
+        result.push('\n');
+    };
+
+    for text in &expected_lines[first.saturating_sub(DIFF_CONTEXT)..first] {
+        line(&mut result, ' ', text);
+    }
+    for text in expected_lines[first..expected_end].iter().take(DIFF_LINES) {
+        line(&mut result, '-', text);
+    }
+    for text in actual_lines[first..actual_end].iter().take(DIFF_LINES) {
+        line(&mut result, '+', text);
+    }
+    for text in expected_lines[expected_end..].iter().take(DIFF_CONTEXT) {
+        line(&mut result, ' ', text);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_shows_first_difference_with_context() {
+        let result = diff(b"a\nb\nc\nd\ne\nf\n", b"a\nb\nc\nd\nx\nf\n");
+
+        assert_eq!(
+            result,
+            [
+                "Output differs at line 5 (expected 12 bytes, got 12 bytes)",
+                " b\\n",
+                " c\\n",
+                " d\\n",
+                "-e\\n",
+                "+x\\n",
+                " f\\n",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn diff_shows_missing_output() {
+        let result = diff(b"a\nb", b"a\n");
+
+        assert_eq!(
+            result,
+            "Output differs at line 2 (expected 3 bytes, got 2 bytes)\n a\\n\n-b\n"
+        );
+    }
+
+    #[test]
+    fn discover_pairs_sources_with_expected_output() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+
+        let cases = discover(&directory).unwrap();
+
+        let hw = cases.iter().find(|c| c.name == "hw").unwrap();
+        assert_eq!(hw.expected, b"Hello World!\n");
+        assert!(hw.complete);
+        assert!(hw.input.is_empty());
+    }
+}
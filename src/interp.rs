@@ -1,16 +1,189 @@
 //! A Brainfuck interpreter that supports breakpoints.
 
+use crate::expr::Expr;
 use crate::ir::BrainfuckInstruction;
-use std::collections::{HashMap, HashSet};
-use std::io::{stdin, stdout, Read, Write};
+use crate::parser;
+use crate::profile::Profile;
+use crate::snapshot::Snapshot;
+use crate::trace::{Record, Tracer};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::io::{self, stdin, stdout, ErrorKind, Read, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The number of instructions executed between checks of the wall-clock deadline in `run`.
+const DEADLINE_CHECK_INTERVAL: u64 = 4096;
+
+/// A breakpoint on a single code address.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Breakpoint {
+    /// An expression that must evaluate to a nonzero value for the breakpoint to stop the Interpreter.
+    /// A condition that fails to evaluate always stops the Interpreter.
+    pub condition: Option<Expr>,
+    /// The number of times the breakpoint will be passed over before it stops the Interpreter.
+    pub ignore_count: u64,
+    /// The number of times the breakpoint has stopped the Interpreter.
+    pub hit_count: u64,
+}
+
+/// WatchKind represents the kind of access to a tape cell that triggers a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WatchKind {
+    /// Read triggers when an instruction reads the cell.
+    Read,
+    /// Write triggers when an instruction writes to the cell, even if its value stays the same.
+    Write,
+    /// Change triggers when the value of the cell changes.
+    Change,
+    /// Equals triggers when the value of the cell changes to the given value.
+    Equals(u8),
+}
+
+/// A data watchpoint on a single tape cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Watchpoint {
+    /// The tape address being watched.
+    pub address: usize,
+    /// The kind of access that triggers the watchpoint.
+    pub kind: WatchKind,
+}
 
 /// StopReason represents a reason the Interpreter might stop running.
 #[derive(Debug, PartialEq)]
 pub enum StopReason {
     /// Breakpoint means that the Interpreter tried to execute an instruction that has a breakpoint set on it.
     Breakpoint(usize),
+    /// Watchpoint means that the last instruction executed accessed a tape cell in a way that triggers a watchpoint.
+    Watchpoint(Watchpoint),
     /// Done means that the Interpreter finished executing the Brainfuck program.
     Done,
+    /// BudgetExhausted means that the Interpreter executed as many instructions as its step budget allows.
+    BudgetExhausted,
+    /// HistoryExhausted means that reverse execution reached the oldest recorded step.
+    HistoryExhausted,
+    /// Timeout means that the Interpreter ran for longer than its timeout allows.
+    Timeout,
+    /// Interrupted means that the flag given to `Interpreter::set_interrupt` was set while the Interpreter was running.
+    Interrupted,
+    /// Stepped means that the Interpreter executed the requested number of instructions,
+    /// or arrived at the code address it was asked to run to.
+    Stepped,
+}
+
+/// An in-memory output sink that can be shared with an Interpreter via `Interpreter::set_output`.
+#[derive(Clone, Default)]
+pub struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl OutputBuffer {
+    /// Creates a new, empty OutputBuffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of everything written to the buffer so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    /// Returns the number of bytes written to the buffer so far.
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    /// Returns true if nothing has been written to the buffer yet.
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    /// Removes and returns everything written to the buffer so far.
+    pub fn take(&self) -> Vec<u8> {
+        self.0.borrow_mut().split_off(0)
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A queue of input bytes that can be shared with an Interpreter via `Interpreter::set_input`.
+/// Once the queue is empty, reads fall through to another reader.
+#[derive(Clone)]
+pub struct InputQueue(Rc<RefCell<QueuedInput>>);
+
+struct QueuedInput {
+    queue: VecDeque<u8>,
+    fallback: Box<dyn Read>,
+}
+
+impl InputQueue {
+    /// Creates a new, empty InputQueue.
+    ///
+    /// # Arguments
+    ///
+    /// * `fallback` - Where input is read from once the queue is empty.
+    pub fn new(fallback: Box<dyn Read>) -> Self {
+        Self(Rc::new(RefCell::new(QueuedInput {
+            queue: VecDeque::new(),
+            fallback,
+        })))
+    }
+
+    /// Adds bytes to the end of the queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The bytes to add.
+    pub fn push(&self, bytes: &[u8]) {
+        self.0.borrow_mut().queue.extend(bytes);
+    }
+
+    /// Returns a copy of the bytes in the queue, in the order they will be read.
+    pub fn queued(&self) -> Vec<u8> {
+        self.0.borrow().queue.iter().copied().collect()
+    }
+
+    /// Removes every byte from the queue.
+    pub fn clear(&self) {
+        self.0.borrow_mut().queue.clear();
+    }
+}
+
+impl Read for InputQueue {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let QueuedInput { queue, fallback } = &mut *self.0.borrow_mut();
+        if queue.is_empty() {
+            return fallback.read(buf);
+        }
+
+        let count = buf.len().min(queue.len());
+        for (slot, byte) in buf.iter_mut().zip(queue.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+}
+
+/// The number of bytes of memory each step recorded for reverse execution takes.
+pub const HISTORY_STEP_SIZE: usize = std::mem::size_of::<Change>();
+
+/// The state needed to undo a single step.
+/// Every instruction writes at most the cell under the data pointer, so that is the only cell recorded.
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    instruction_pointer: usize,
+    data_pointer: usize,
+    value: u8,
+    input: Option<u8>,
 }
 
 /// A Brainfuck interpreter that supports breakpoints.
@@ -19,8 +192,21 @@ pub struct Interpreter {
     tape: Vec<u8>,
     data_pointer: usize,
     instruction_pointer: usize,
-    breakpoints: HashSet<usize>,
+    breakpoints: HashMap<usize, Breakpoint>,
+    watchpoints: BTreeSet<Watchpoint>,
     jump_table: HashMap<usize, usize>,
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+    steps: u64,
+    step_budget: Option<u64>,
+    timeout: Option<Duration>,
+    interrupt: Option<Arc<AtomicBool>>,
+    history: VecDeque<Change>,
+    history_limit: usize,
+    pending_input: Vec<u8>,
+    pending_output: Vec<u8>,
+    tracer: Option<Tracer>,
+    profile: Option<Profile>,
 }
 
 impl Interpreter {
@@ -35,8 +221,21 @@ impl Interpreter {
             tape: vec![0u8; 3000],
             data_pointer: 0,
             instruction_pointer: 0,
-            breakpoints: HashSet::new(),
+            breakpoints: HashMap::new(),
+            watchpoints: BTreeSet::new(),
             jump_table: HashMap::new(),
+            input: Box::new(stdin()),
+            output: Box::new(stdout()),
+            steps: 0,
+            step_budget: None,
+            timeout: None,
+            interrupt: None,
+            history: VecDeque::new(),
+            history_limit: 0,
+            pending_input: Vec::new(),
+            pending_output: Vec::new(),
+            tracer: None,
+            profile: None,
         };
         result.compute_jump_table();
         result
@@ -63,6 +262,106 @@ impl Interpreter {
         }
     }
 
+    /// Replaces the source that `,` instructions read from. Defaults to stdin.
+    /// Reading past the end of the input leaves the current cell unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The new input source.
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.input = input;
+    }
+
+    /// Replaces the sink that `.` instructions write to. Defaults to stdout.
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The new output sink.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Limits the number of instructions a single call to `run` may execute.
+    /// When the budget runs out, `run` returns `StopReason::BudgetExhausted` and can be called again to continue.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget` - The maximum number of instructions per call to `run`, or `None` for no limit.
+    pub fn set_step_budget(&mut self, budget: Option<u64>) {
+        self.step_budget = budget;
+    }
+
+    /// Limits the wall-clock time a single call to `run` may take.
+    /// When the time runs out, `run` returns `StopReason::Timeout` and can be called again to continue.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The maximum duration of a call to `run`, or `None` for no limit.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Lets another thread, such as a signal handler, stop `run` by setting a flag.
+    /// When the flag is set, `run` clears it and returns `StopReason::Interrupted`, and can be called again to continue.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag` - The flag to check between instructions, or `None` to stop checking.
+    pub fn set_interrupt(&mut self, flag: Option<Arc<AtomicBool>>) {
+        self.interrupt = flag;
+    }
+
+    /// Limits the number of steps recorded for reverse execution. Recording is disabled by default.
+    /// When the limit is reached, the oldest steps are forgotten.
+    /// Every recorded step takes `HISTORY_STEP_SIZE` bytes, so the limit also bounds the memory the history uses.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The maximum number of steps to remember, or 0 to disable recording.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+        while self.history.len() > limit {
+            self.history.pop_front();
+        }
+    }
+
+    /// Returns the number of steps that can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Starts or stops recording a trace of executed steps.
+    ///
+    /// # Arguments
+    ///
+    /// * `tracer` - The Tracer to record steps with, or `None` to stop tracing.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Stops tracing and returns the Tracer that was in use, so that it can be flushed.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Turns profiling on or off. Turning profiling on starts a new, empty Profile.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to count executions of instructions and iterations of loops.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = if enabled {
+            Some(Profile::new(self.code.len()))
+        } else {
+            None
+        };
+    }
+
+    /// Returns the Profile collected so far, if profiling is on.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
     /// Sets a breakpoint at the specified code address.
     ///
     /// # Arguments
@@ -72,34 +371,327 @@ impl Interpreter {
         if address >= self.code.len() {
             Err(format!("Address out of bounds: {}", address))
         } else {
-            Ok(self.breakpoints.insert(address))
+            let new = !self.breakpoints.contains_key(&address);
+            self.breakpoints.entry(address).or_default();
+            Ok(new)
+        }
+    }
+
+    /// Sets the condition of the breakpoint at the specified code address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The code address of the breakpoint.
+    /// * `condition` - The condition under which the breakpoint stops the Interpreter, or `None` to always stop.
+    pub fn set_breakpoint_condition(&mut self, address: usize, condition: Option<Expr>) -> Result<(), String> {
+        match self.breakpoints.get_mut(&address) {
+            Some(breakpoint) => {
+                breakpoint.condition = condition;
+                Ok(())
+            }
+            None => Err(format!("No breakpoint at {}", address)),
         }
     }
 
+    /// Sets the number of times the breakpoint at the specified code address will be passed over.
+    /// Only passes where the condition of the breakpoint holds are counted.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The code address of the breakpoint.
+    /// * `count` - The number of times to pass over the breakpoint.
+    pub fn set_breakpoint_ignore_count(&mut self, address: usize, count: u64) -> Result<(), String> {
+        match self.breakpoints.get_mut(&address) {
+            Some(breakpoint) => {
+                breakpoint.ignore_count = count;
+                Ok(())
+            }
+            None => Err(format!("No breakpoint at {}", address)),
+        }
+    }
+
+    /// Returns the breakpoint at the specified code address, if there is one.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The code address of the breakpoint.
+    pub fn breakpoint(&self, address: usize) -> Option<&Breakpoint> {
+        self.breakpoints.get(&address)
+    }
+
+    /// Returns all breakpoints, ordered by code address.
+    pub fn breakpoints(&self) -> Vec<(usize, &Breakpoint)> {
+        let mut result: Vec<(usize, &Breakpoint)> = self.breakpoints.iter().map(|(a, b)| (*a, b)).collect();
+        result.sort_by_key(|(address, _)| *address);
+        result
+    }
+
     /// Removes a breakpoint at the specified code address.
     ///
     /// # Arguments
     ///
     /// * `address` - The code address to delete a breakpoint from.
     pub fn delete_breakpoint(&mut self, address: usize) -> bool {
-        self.breakpoints.remove(&address)
+        self.breakpoints.remove(&address).is_some()
     }
 
-    /// Runs the Interpreter until either a breakpoint is hit or until the program has run to completion.
+    /// Sets a watchpoint on the specified tape address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The tape address to watch.
+    /// * `kind` - The kind of access that should trigger the watchpoint.
+    pub fn set_watchpoint(&mut self, address: usize, kind: WatchKind) -> Result<bool, String> {
+        if address >= self.tape.len() {
+            Err(format!("Address out of bounds: {}", address))
+        } else {
+            Ok(self.watchpoints.insert(Watchpoint { address, kind }))
+        }
+    }
+
+    /// Removes watchpoints from the specified tape address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The tape address to stop watching.
+    /// * `kind` - The kind of watchpoint to remove, or `None` to remove every watchpoint on the address.
+    pub fn delete_watchpoint(&mut self, address: usize, kind: Option<WatchKind>) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints
+            .retain(|w| w.address != address || kind.is_some_and(|kind| kind != w.kind));
+        self.watchpoints.len() != before
+    }
+
+    /// Returns all watchpoints, ordered by address.
+    pub fn watchpoints(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter()
+    }
+
+    /// Runs the Interpreter until either a breakpoint or watchpoint is hit, the step budget or timeout runs out,
+    /// or until the program has run to completion.
     pub fn run(&mut self) -> StopReason {
+        self.execute(None, None)
+    }
+
+    /// Executes up to `count` instructions, stopping early at breakpoints and watchpoints.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - The number of instructions to execute.
+    pub fn step_many(&mut self, count: u64) -> StopReason {
+        self.execute(None, Some(count))
+    }
+
+    /// Executes a single instruction, except that a loop which is about to be entered is run until it exits.
+    pub fn step_over(&mut self) -> StopReason {
+        match self.step_over_target() {
+            Some(target) => self.execute(Some(target), None),
+            None => self.execute(None, Some(1)),
+        }
+    }
+
+    /// Returns the code address `step_over` runs to, or None if it executes a single instruction.
+    pub fn step_over_target(&self) -> Option<usize> {
+        match self.code.get(self.instruction_pointer) {
+            Some(BrainfuckInstruction::Open) => Some(self.jump_table[&self.instruction_pointer]),
+            _ => None,
+        }
+    }
+
+    /// Runs the Interpreter until the innermost loop containing the instruction pointer exits.
+    pub fn finish(&mut self) -> Result<StopReason, String> {
+        let target = self.finish_target()?;
+        Ok(self.execute(Some(target), None))
+    }
+
+    /// Returns the code address `finish` runs to, just past the innermost loop containing the instruction pointer.
+    pub fn finish_target(&self) -> Result<usize, String> {
+        let mut depth = 0;
+        for address in (0..self.instruction_pointer.min(self.code.len())).rev() {
+            match self.code[address] {
+                BrainfuckInstruction::Close => depth += 1,
+                BrainfuckInstruction::Open if depth == 0 => return Ok(self.jump_table[&address]),
+                BrainfuckInstruction::Open => depth -= 1,
+                _ => {}
+            }
+        }
+
+        Err(String::from("Not inside a loop"))
+    }
+
+    /// Runs the Interpreter until the instruction pointer arrives at the specified code address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The code address to run to.
+    pub fn run_until(&mut self, address: usize) -> Result<StopReason, String> {
+        if address >= self.code.len() {
+            Err(format!("Address out of bounds: {}", address))
+        } else {
+            Ok(self.execute(Some(address), None))
+        }
+    }
+
+    /// Executes up to `count` instructions like `step_many`, but also stops when the instruction pointer arrives at
+    /// `address`. This lets front ends run to a target in chunks, checking for a pause between them.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The code address to run to, which may be the end of the code.
+    /// * `count` - The maximum number of instructions to execute.
+    pub fn step_until(&mut self, address: usize, count: u64) -> StopReason {
+        self.execute(Some(address), Some(count))
+    }
+
+    /// Runs the Interpreter like `run`, but also stops when the instruction pointer arrives at `target`
+    /// or when `count` instructions have been executed.
+    fn execute(&mut self, target: Option<usize>, count: Option<u64>) -> StopReason {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut executed = 0;
+
         while self.instruction_pointer < self.code.len() {
+            if count == Some(executed) {
+                return StopReason::Stepped;
+            }
+            if let Some(budget) = self.step_budget {
+                if executed >= budget {
+                    return StopReason::BudgetExhausted;
+                }
+            }
+            if let Some(deadline) = deadline {
+                if executed % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                    return StopReason::Timeout;
+                }
+            }
+            if self.interrupted() {
+                return StopReason::Interrupted;
+            }
+
+            let instruction_pointer = self.instruction_pointer;
+            let data_pointer = self.data_pointer;
+            let old = self.tape.get(data_pointer).cloned();
+
             self.step();
+            executed += 1;
+
+            if !self.watchpoints.is_empty() {
+                if let Some(watchpoint) = self.triggered_watchpoint(instruction_pointer, data_pointer, old) {
+                    return StopReason::Watchpoint(watchpoint);
+                }
+            }
 
-            if self.breakpoints.contains(&self.instruction_pointer) {
+            if self.breakpoints.contains_key(&self.instruction_pointer) && self.hit_breakpoint() {
                 return StopReason::Breakpoint(self.instruction_pointer);
             }
+
+            // Arriving at a target at the end of the code finishes the program.
+            if target == Some(self.instruction_pointer) && !self.is_done() {
+                return StopReason::Stepped;
+            }
         }
         StopReason::Done
     }
 
+    /// Returns true and clears the interrupt flag if it has been set.
+    fn interrupted(&self) -> bool {
+        match &self.interrupt {
+            Some(flag) if flag.load(Ordering::Relaxed) => {
+                flag.store(false, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if there is a breakpoint at the instruction pointer and its condition holds.
+    /// Ignore counts are not considered.
+    fn breakpoint_condition_holds(&self) -> bool {
+        match self.breakpoints.get(&self.instruction_pointer) {
+            Some(Breakpoint {
+                condition: Some(condition),
+                ..
+            }) => condition.evaluate(self) != Ok(0),
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    fn hit_breakpoint(&mut self) -> bool {
+        if !self.breakpoint_condition_holds() {
+            return false;
+        }
+
+        let breakpoint = self.breakpoints.get_mut(&self.instruction_pointer).unwrap();
+        if breakpoint.ignore_count > 0 {
+            breakpoint.ignore_count -= 1;
+            false
+        } else {
+            breakpoint.hit_count += 1;
+            true
+        }
+    }
+
+    fn triggered_watchpoint(&self, instruction_pointer: usize, data_pointer: usize, old: Option<u8>) -> Option<Watchpoint> {
+        // Only the cell under the data pointer can be written, but scans read every cell they pass over.
+        let (first, last, stride) = match self.code[instruction_pointer] {
+            BrainfuckInstruction::ScanLeft(stride) => (self.data_pointer, data_pointer, stride),
+            BrainfuckInstruction::ScanRight(stride) => (data_pointer, self.data_pointer, stride),
+            _ => (data_pointer, data_pointer, 1),
+        };
+        let (reads, writes) = match self.code[instruction_pointer] {
+            BrainfuckInstruction::Add(_) | BrainfuckInstruction::Sub(_) => (true, true),
+            BrainfuckInstruction::Read | BrainfuckInstruction::Set(_) => (false, true),
+            BrainfuckInstruction::Write
+            | BrainfuckInstruction::Open
+            | BrainfuckInstruction::Close
+            | BrainfuckInstruction::ScanLeft(_)
+            | BrainfuckInstruction::ScanRight(_) => (true, false),
+            BrainfuckInstruction::Right(_)
+            | BrainfuckInstruction::Left(_)
+            | BrainfuckInstruction::Print(_) => (false, false),
+        };
+        let new = self.tape.get(data_pointer).cloned();
+
+        self.watchpoints
+            .iter()
+            .find(|w| {
+                let read = reads && w.address >= first && w.address <= last && (w.address - first) % stride == 0;
+                let written = writes && w.address == data_pointer;
+                let changed = written && old != new;
+                match w.kind {
+                    WatchKind::Read => read,
+                    WatchKind::Write => written,
+                    WatchKind::Change => changed,
+                    WatchKind::Equals(value) => changed && new == Some(value),
+                }
+            })
+            .cloned()
+    }
+
     /// Executes a single BrainfuckInstruction.
     pub fn step(&mut self) {
         let mut next_instruction_pointer = self.instruction_pointer + 1;
+        let data_pointer = self.data_pointer;
+        let before = self.tape.get(data_pointer).cloned().unwrap_or(0);
+        let mut input = None;
+        let mut output = Vec::new();
+        self.steps += 1;
+
+        if let Some(profile) = self.profile.as_mut() {
+            profile.counts[self.instruction_pointer] += 1;
+        }
+
+        if self.history_limit > 0 {
+            if self.history.len() == self.history_limit {
+                self.history.pop_front();
+            }
+            self.history.push_back(Change {
+                instruction_pointer: self.instruction_pointer,
+                data_pointer: self.data_pointer,
+                value: before,
+                input: None,
+            });
+        }
 
         match self.code[self.instruction_pointer] {
             BrainfuckInstruction::Add(count) => {
@@ -111,40 +703,249 @@ impl Interpreter {
             BrainfuckInstruction::Right(count) => self.data_pointer += count,
             BrainfuckInstruction::Left(count) => self.data_pointer -= count,
             BrainfuckInstruction::Read => {
-                self.tape[self.data_pointer] = stdin().bytes().take(1).last().unwrap().unwrap()
+                let byte = match self.pending_input.pop() {
+                    Some(byte) => Some(byte),
+                    None => {
+                        let mut buffer = [0u8; 1];
+                        match self.input.read_exact(&mut buffer) {
+                            Ok(()) => Some(buffer[0]),
+                            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => None,
+                            Err(e) => panic!("Failed to read input: {}", e),
+                        }
+                    }
+                };
+                input = byte;
+                if let Some(byte) = byte {
+                    self.tape[self.data_pointer] = byte;
+                    if self.history_limit > 0 {
+                        self.history.back_mut().unwrap().input = Some(byte);
+                    }
+                }
             }
             BrainfuckInstruction::Write => {
-                print!("{}", self.tape[self.data_pointer] as char);
-                stdout().flush().unwrap();
+                // Output is not written twice when stepping forwards again after stepping back over it.
+                let byte = self.tape[self.data_pointer];
+                if self.pending_output.pop() != Some(byte) {
+                    self.pending_output.clear();
+                    self.output.write_all(&[byte]).unwrap();
+                    self.output.flush().unwrap();
+                    output.push(byte);
+                }
             }
             BrainfuckInstruction::Open => {
                 if self.tape[self.data_pointer] == 0 {
                     next_instruction_pointer = self.jump_table[&self.instruction_pointer];
+                } else if let Some(profile) = self.profile.as_mut() {
+                    profile.iterations[self.instruction_pointer] += 1;
                 }
             }
             BrainfuckInstruction::Close => {
                 if self.tape[self.data_pointer] != 0 {
                     next_instruction_pointer = self.jump_table[&self.instruction_pointer];
+                    if let Some(profile) = self.profile.as_mut() {
+                        profile.iterations[next_instruction_pointer - 1] += 1;
+                    }
                 }
             }
             BrainfuckInstruction::Set(value) => {
                 self.tape[self.data_pointer] = value;
             }
-            BrainfuckInstruction::ScanRight => {
-                while self.tape[self.data_pointer] != 0 && self.data_pointer < self.tape.len() {
-                    self.data_pointer += 1;
-                }
+            BrainfuckInstruction::ScanRight(stride) => {
+                let offset = self.tape[self.data_pointer..]
+                    .iter()
+                    .step_by(stride)
+                    .position(|x| *x == 0)
+                    .expect("Scan ran off the end of the tape");
+                self.data_pointer += offset * stride;
+            }
+            BrainfuckInstruction::ScanLeft(stride) => {
+                let offset = self.tape[..=self.data_pointer]
+                    .iter()
+                    .rev()
+                    .step_by(stride)
+                    .position(|x| *x == 0)
+                    .expect("Scan ran off the start of the tape");
+                self.data_pointer -= offset * stride;
             }
-            BrainfuckInstruction::ScanLeft => {
-                while self.tape[self.data_pointer] != 0 && self.data_pointer > 0 {
-                    self.data_pointer -= 1;
+            BrainfuckInstruction::Print(ref bytes) => {
+                let pending = self.pending_output.len();
+                if pending >= bytes.len() && self.pending_output[pending - bytes.len()..].iter().rev().eq(bytes) {
+                    self.pending_output.truncate(pending - bytes.len());
+                } else {
+                    self.pending_output.clear();
+                    self.output.write_all(bytes).unwrap();
+                    self.output.flush().unwrap();
+                    output.extend_from_slice(bytes);
                 }
             }
         }
 
+        let steps = self.steps;
+        if let Some(tracer) = self.tracer.as_mut().filter(|tracer| tracer.wants(steps)) {
+            let instruction = self.code[self.instruction_pointer].clone();
+            let record = Record {
+                step: self.steps,
+                instruction_pointer: self.instruction_pointer,
+                instruction,
+                data_pointer,
+                before,
+                after: self.tape.get(data_pointer).cloned().unwrap_or(0),
+                input,
+                output,
+            };
+            tracer.record(&record).expect("Failed to write trace");
+        }
+
         self.instruction_pointer = next_instruction_pointer;
     }
 
+    /// Undoes the most recently executed step, restoring the pointers and tape.
+    /// Input read by the step is pushed back to be read again. Output cannot be taken back, so it is remembered
+    /// instead and not written again when the step is executed again, unless the program then writes something
+    /// different. Returns false if there is no recorded history to undo.
+    pub fn step_back(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some(change) => {
+                self.instruction_pointer = change.instruction_pointer;
+                self.data_pointer = change.data_pointer;
+                if let Some(cell) = self.tape.get_mut(change.data_pointer) {
+                    *cell = change.value;
+                }
+                if let Some(byte) = change.input {
+                    self.pending_input.push(byte);
+                }
+                match &self.code[change.instruction_pointer] {
+                    BrainfuckInstruction::Write => self.pending_output.push(change.value),
+                    BrainfuckInstruction::Print(bytes) => self.pending_output.extend(bytes.iter().rev()),
+                    _ => {}
+                }
+                self.steps -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs the Interpreter backwards until it arrives at a breakpoint whose condition holds,
+    /// or until the recorded history runs out. Ignore counts and watchpoints are not considered.
+    pub fn reverse_run(&mut self) -> StopReason {
+        while self.step_back() {
+            if self.interrupted() {
+                return StopReason::Interrupted;
+            }
+            if self.breakpoint_condition_holds() {
+                return StopReason::Breakpoint(self.instruction_pointer);
+            }
+        }
+
+        StopReason::HistoryExhausted
+    }
+
+    /// Runs the Interpreter backwards until exactly `steps` instructions have been executed.
+    ///
+    /// # Arguments
+    ///
+    /// * `steps` - The earlier instruction count to rewind to.
+    pub fn rewind(&mut self, steps: u64) -> Result<(), String> {
+        if steps > self.steps {
+            Err(format!("Cannot rewind forwards to {}", steps))
+        } else if self.steps - steps > self.history.len() as u64 {
+            Err(format!(
+                "History only goes back to {}",
+                self.steps - self.history.len() as u64
+            ))
+        } else {
+            while self.steps > steps {
+                self.step_back();
+            }
+            Ok(())
+        }
+    }
+
+    /// Captures the current state of the Interpreter.
+    /// Execution history, profiling data and the input and output streams are not included.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            code: self.code.clone(),
+            tape: self.tape.clone(),
+            data_pointer: self.data_pointer,
+            instruction_pointer: self.instruction_pointer,
+            steps: self.steps,
+            breakpoints: self.breakpoints.iter().map(|(a, b)| (*a, b.clone())).collect(),
+            watchpoints: self.watchpoints.iter().copied().collect(),
+            pending_input: self.pending_input.iter().rev().copied().collect(),
+        }
+    }
+
+    /// Replaces the state of the Interpreter with a Snapshot.
+    /// The input and output streams and any tracer are kept, while the execution history is cleared
+    /// and profiling, if enabled, starts over.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The Snapshot to restore.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        parser::check_brackets(&snapshot.code)?;
+        if snapshot.data_pointer >= snapshot.tape.len() {
+            return Err(format!("Address out of bounds: {}", snapshot.data_pointer));
+        }
+        if snapshot.instruction_pointer > snapshot.code.len() {
+            return Err(format!("Address out of bounds: {}", snapshot.instruction_pointer));
+        }
+        if let Some(address) = snapshot.breakpoints.keys().find(|a| **a >= snapshot.code.len()) {
+            return Err(format!("Address out of bounds: {}", address));
+        }
+        if let Some(watchpoint) = snapshot.watchpoints.iter().find(|w| w.address >= snapshot.tape.len()) {
+            return Err(format!("Address out of bounds: {}", watchpoint.address));
+        }
+
+        self.code = snapshot.code;
+        self.tape = snapshot.tape;
+        self.data_pointer = snapshot.data_pointer;
+        self.instruction_pointer = snapshot.instruction_pointer;
+        self.steps = snapshot.steps;
+        self.breakpoints = snapshot.breakpoints.into_iter().collect();
+        self.watchpoints = snapshot.watchpoints.into_iter().collect();
+        self.pending_input = snapshot.pending_input.into_iter().rev().collect();
+        self.forget_history();
+        if self.profile.is_some() {
+            self.profile = Some(Profile::new(self.code.len()));
+        }
+        self.jump_table.clear();
+        self.compute_jump_table();
+        Ok(())
+    }
+
+    /// Returns the program being interpreted.
+    pub fn code(&self) -> &[BrainfuckInstruction] {
+        &self.code
+    }
+
+    /// Returns true if the Interpreter has finished executing the Brainfuck program.
+    pub fn is_done(&self) -> bool {
+        self.instruction_pointer >= self.code.len()
+    }
+
+    /// Returns the total number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Returns the entire tape.
+    pub fn tape(&self) -> &[u8] {
+        &self.tape
+    }
+
+    /// Returns the current data pointer.
+    pub fn data_pointer(&self) -> usize {
+        self.data_pointer
+    }
+
+    /// Returns the current instruction pointer.
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
+
     /// Reads a value from the tape at the specified address.
     ///
     /// # Arguments
@@ -158,7 +959,7 @@ impl Interpreter {
         }
     }
 
-    /// Writes a value to the tape at the specified address.
+    /// Writes a value to the tape at the specified address, forgetting the execution history.
     ///
     /// # Arguments
     ///
@@ -170,11 +971,12 @@ impl Interpreter {
         } else {
             let old = self.tape[address];
             self.tape[address] = value;
+            self.forget_history();
             Ok(old)
         }
     }
 
-    /// Sets the instruction pointer to the specified code address.
+    /// Sets the instruction pointer to the specified code address, forgetting the execution history.
     ///
     /// # Arguments
     ///
@@ -184,11 +986,12 @@ impl Interpreter {
             Err(format!("Address out of bounds: {}", address))
         } else {
             self.instruction_pointer = address;
+            self.forget_history();
             Ok(())
         }
     }
 
-    /// Sets the data pointer to the specified tape address.
+    /// Sets the data pointer to the specified tape address, forgetting the execution history.
     ///
     /// # Arguments
     ///
@@ -198,14 +1001,24 @@ impl Interpreter {
             Err(format!("Address out of bounds: {}", address))
         } else {
             self.data_pointer = address;
+            self.forget_history();
             Ok(())
         }
     }
+
+    /// Forgets the execution history after the state was changed by something other than a step,
+    /// since undoing the recorded steps would no longer lead back to earlier states.
+    fn forget_history(&mut self) {
+        self.history.clear();
+        self.pending_output.clear();
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::expr;
+    use crate::optimizer;
     use crate::parser;
 
     #[test]
@@ -213,20 +1026,89 @@ mod tests {
         let code = parser::parse_str(String::from("++[>++<-]"));
         let mut subject = Interpreter::new(code);
 
-        subject.set_breakpoint(3);
+        subject.set_breakpoint(3).unwrap();
 
-        assert!(subject.breakpoints.contains(&3))
+        assert!(subject.breakpoints.contains_key(&3))
     }
 
     #[test]
     fn delete_breakpoint_works() {
         let code = parser::parse_str(String::from("++[>++<-]"));
         let mut subject = Interpreter::new(code);
-        subject.breakpoints.insert(3);
+        subject.breakpoints.insert(3, Breakpoint::default());
 
         subject.delete_breakpoint(3);
 
-        assert!(!subject.breakpoints.contains(&3));
+        assert!(!subject.breakpoints.contains_key(&3));
+    }
+
+    #[test]
+    fn step_many_works() {
+        let code = parser::parse_str(String::from("++[>++<-]"));
+        let mut subject = Interpreter::new(code);
+
+        assert_eq!(subject.step_many(3), StopReason::Stepped);
+        assert_eq!(subject.instruction_pointer, 3);
+        assert_eq!(subject.steps, 3);
+        assert_eq!(subject.step_many(100), StopReason::Done);
+    }
+
+    #[test]
+    fn step_over_runs_whole_loops() {
+        let code = parser::parse_str(String::from("++[>+[>+<-]<-]>"));
+        let mut subject = Interpreter::new(code);
+        subject.step_many(2);
+
+        assert_eq!(subject.step_over(), StopReason::Stepped);
+        assert_eq!(subject.instruction_pointer, 14);
+        assert_eq!(subject.tape[..3], [0, 0, 2]);
+
+        subject.jump(2).unwrap();
+        subject.set_breakpoint(7).unwrap();
+        subject.set(0, 1).unwrap();
+        assert_eq!(subject.step_over(), StopReason::Breakpoint(7));
+    }
+
+    #[test]
+    fn finish_leaves_the_innermost_loop() {
+        let code = parser::parse_str(String::from("++[>+[>+<-]<-]>"));
+        let mut subject = Interpreter::new(code);
+
+        assert_eq!(subject.finish(), Err(String::from("Not inside a loop")));
+
+        subject.step_many(7);
+        assert_eq!(subject.finish(), Ok(StopReason::Stepped));
+        assert_eq!(subject.instruction_pointer, 11);
+        assert_eq!(subject.tape[..3], [2, 0, 1]);
+
+        assert_eq!(subject.finish(), Ok(StopReason::Stepped));
+        assert_eq!(subject.instruction_pointer, 14);
+    }
+
+    #[test]
+    fn run_until_works() {
+        let code = parser::parse_str(String::from("++[>++<-]"));
+        let mut subject = Interpreter::new(code);
+
+        assert_eq!(subject.run_until(8), Ok(StopReason::Stepped));
+        assert_eq!(subject.tape[..2], [1, 2]);
+        assert_eq!(subject.run_until(8), Ok(StopReason::Stepped));
+        assert_eq!(subject.tape[..2], [0, 4]);
+        assert_eq!(subject.run_until(9), Err(String::from("Address out of bounds: 9")));
+    }
+
+    #[test]
+    fn step_until_stops_at_the_target_or_after_count_instructions() {
+        let code = parser::parse_str(String::from("++[>++<-]"));
+        let mut subject = Interpreter::new(code);
+
+        assert_eq!(subject.step_over_target(), None);
+        subject.step_many(2);
+        assert_eq!(subject.step_over_target(), Some(9));
+        assert_eq!(subject.step_until(9, 5), StopReason::Stepped);
+        assert_eq!(subject.instruction_pointer, 7);
+        assert_eq!(subject.finish_target(), Ok(9));
+        assert_eq!(subject.step_until(9, 100), StopReason::Done);
     }
 
     #[test]
@@ -247,7 +1129,7 @@ mod tests {
         let code = parser::parse_str(String::from("++[>++<-]"));
         let mut subject = Interpreter::new(code);
 
-        subject.set_breakpoint(1);
+        subject.set_breakpoint(1).unwrap();
 
         assert_eq!(subject.run(), StopReason::Breakpoint(1));
         assert_eq!(subject.run(), StopReason::Done);
@@ -343,4 +1225,324 @@ mod tests {
         assert_eq!(subject.select(100000000), Err(String::from("Address out of bounds: 100000000")));
         assert_eq!(subject.data_pointer, 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn step_reads_from_input() {
+        let code = parser::parse_str(String::from(",>,>,"));
+        let mut subject = Interpreter::new(code);
+        subject.set_input(Box::new(&b"ab"[..]));
+        subject.tape[2] = 42;
+
+        assert_eq!(subject.run(), StopReason::Done);
+
+        assert_eq!(&subject.tape[0..3], &[97, 98, 42]);
+    }
+
+    #[test]
+    fn input_queue_is_read_before_its_fallback() {
+        let code = parser::parse_str(String::from(",>,>,>,"));
+        let mut subject = Interpreter::new(code);
+        let input = InputQueue::new(Box::new(&b"c"[..]));
+        input.push(b"a");
+        subject.set_input(Box::new(input.clone()));
+
+        assert_eq!(subject.step_many(2), StopReason::Stepped);
+        input.push(b"b");
+        assert_eq!(input.queued(), b"b");
+        assert_eq!(subject.run(), StopReason::Done);
+
+        assert_eq!(&subject.tape[0..4], &[97, 98, 99, 0]);
+    }
+
+    #[test]
+    fn step_writes_to_output() {
+        let code = parser::parse_str(String::from("+++.+."));
+        let mut subject = Interpreter::new(code);
+        let output = OutputBuffer::new();
+        subject.set_output(Box::new(output.clone()));
+
+        assert_eq!(subject.run(), StopReason::Done);
+
+        assert_eq!(output.contents(), vec![3, 4]);
+    }
+
+    #[test]
+    fn run_stops_when_budget_is_exhausted() {
+        let code = parser::parse_str(String::from("++++"));
+        let mut subject = Interpreter::new(code);
+        subject.set_step_budget(Some(3));
+
+        assert_eq!(subject.run(), StopReason::BudgetExhausted);
+        assert_eq!(subject.tape[0], 3);
+        assert_eq!(subject.steps(), 3);
+
+        assert_eq!(subject.run(), StopReason::Done);
+        assert_eq!(subject.tape[0], 4);
+        assert_eq!(subject.steps(), 4);
+    }
+
+    #[test]
+    fn run_stops_when_interrupted() {
+        let code = parser::parse_str(String::from("+[]"));
+        let mut subject = Interpreter::new(code);
+        let flag = Arc::new(AtomicBool::new(false));
+        subject.set_interrupt(Some(flag.clone()));
+
+        let interrupter = {
+            let flag = flag.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                flag.store(true, Ordering::Relaxed);
+            })
+        };
+        assert_eq!(subject.run(), StopReason::Interrupted);
+        interrupter.join().unwrap();
+
+        assert!(!flag.load(Ordering::Relaxed));
+        assert_eq!(subject.instruction_pointer(), 2);
+        assert_eq!(subject.tape[0], 1);
+        subject.set_step_budget(Some(100));
+        assert_eq!(subject.run(), StopReason::BudgetExhausted);
+    }
+
+    #[test]
+    fn run_stops_when_timeout_expires() {
+        let code = parser::parse_str(String::from("+[]"));
+        let mut subject = Interpreter::new(code);
+        subject.set_timeout(Some(Duration::from_millis(10)));
+
+        assert_eq!(subject.run(), StopReason::Timeout);
+        assert_eq!(subject.run(), StopReason::Timeout);
+
+        subject.set_timeout(None);
+        subject.set_step_budget(Some(100));
+        assert_eq!(subject.run(), StopReason::BudgetExhausted);
+    }
+
+    #[test]
+    fn run_stops_for_write_watchpoints() {
+        let code = parser::parse_str(String::from(">+<[-]>[-]"));
+        let mut subject = Interpreter::new(code);
+        let watchpoint = Watchpoint { address: 1, kind: WatchKind::Write };
+
+        assert_eq!(subject.set_watchpoint(1, WatchKind::Write), Ok(true));
+
+        assert_eq!(subject.run(), StopReason::Watchpoint(watchpoint));
+        assert_eq!(subject.instruction_pointer, 2);
+        assert_eq!(subject.run(), StopReason::Watchpoint(watchpoint));
+        assert_eq!(subject.instruction_pointer, 9);
+        assert_eq!(subject.run(), StopReason::Done);
+    }
+
+    #[test]
+    fn run_stops_for_read_watchpoints() {
+        let code = optimizer::optimize(parser::parse_str(String::from(",[-]>>[-]+>>[-]+[<<]")), 10);
+        let mut subject = Interpreter::new(code);
+        subject.set_input(Box::new(&b"\x01"[..]));
+        let watchpoint = Watchpoint { address: 2, kind: WatchKind::Read };
+
+        subject.set_watchpoint(2, WatchKind::Read).unwrap();
+        subject.set_watchpoint(3, WatchKind::Read).unwrap();
+
+        assert_eq!(subject.run(), StopReason::Watchpoint(watchpoint));
+        assert_eq!(subject.data_pointer, 0);
+        assert_eq!(subject.run(), StopReason::Done);
+    }
+
+    #[test]
+    fn run_stops_for_change_and_equals_watchpoints() {
+        let code = parser::parse_str(String::from("+[-]+++"));
+        let mut subject = Interpreter::new(code);
+
+        subject.set_watchpoint(0, WatchKind::Equals(2)).unwrap();
+        subject.set_watchpoint(0, WatchKind::Change).unwrap();
+
+        assert_eq!(subject.run(), StopReason::Watchpoint(Watchpoint { address: 0, kind: WatchKind::Change }));
+        assert_eq!(subject.tape[0], 1);
+
+        subject.delete_watchpoint(0, Some(WatchKind::Change));
+
+        assert_eq!(subject.run(), StopReason::Watchpoint(Watchpoint { address: 0, kind: WatchKind::Equals(2) }));
+        assert_eq!(subject.instruction_pointer, 6);
+        assert_eq!(subject.run(), StopReason::Done);
+    }
+
+    #[test]
+    fn delete_watchpoint_removes_every_kind() {
+        let code = parser::parse_str(String::from("+++"));
+        let mut subject = Interpreter::new(code);
+        subject.set_watchpoint(0, WatchKind::Read).unwrap();
+        subject.set_watchpoint(0, WatchKind::Write).unwrap();
+        subject.set_watchpoint(1, WatchKind::Write).unwrap();
+
+        assert!(subject.delete_watchpoint(0, None));
+
+        assert_eq!(subject.watchpoints().collect::<Vec<_>>(), vec![&Watchpoint { address: 1, kind: WatchKind::Write }]);
+        assert!(!subject.delete_watchpoint(0, None));
+    }
+
+    #[test]
+    fn set_watchpoint_returns_error_when_address_out_of_bounds() {
+        let code = parser::parse_str(String::from("+++"));
+        let mut subject = Interpreter::new(code);
+
+        assert_eq!(subject.set_watchpoint(100000000, WatchKind::Read), Err(String::from("Address out of bounds: 100000000")));
+    }
+
+    #[test]
+    fn run_stops_for_conditional_breakpoints() {
+        let code = parser::parse_str(String::from("+++++[>+<-]"));
+        let mut subject = Interpreter::new(code);
+
+        subject.set_breakpoint(7).unwrap();
+        subject.set_breakpoint_condition(7, Some(expr::parse("*1 == 3").unwrap())).unwrap();
+
+        assert_eq!(subject.run(), StopReason::Breakpoint(7));
+        assert_eq!(subject.tape[1], 3);
+        assert_eq!(subject.run(), StopReason::Done);
+        assert_eq!(subject.breakpoint(7).unwrap().hit_count, 1);
+    }
+
+    #[test]
+    fn run_ignores_breakpoints_until_ignore_count_runs_out() {
+        let code = parser::parse_str(String::from("+++++[>+<-]"));
+        let mut subject = Interpreter::new(code);
+
+        subject.set_breakpoint(7).unwrap();
+        subject.set_breakpoint_ignore_count(7, 2).unwrap();
+
+        assert_eq!(subject.run(), StopReason::Breakpoint(7));
+        assert_eq!(subject.tape[1], 2);
+        assert_eq!(subject.run(), StopReason::Breakpoint(7));
+        assert_eq!(subject.tape[1], 3);
+        assert_eq!(subject.breakpoint(7).unwrap().hit_count, 2);
+    }
+
+    #[test]
+    fn set_breakpoint_condition_returns_error_without_breakpoint() {
+        let code = parser::parse_str(String::from("+++"));
+        let mut subject = Interpreter::new(code);
+
+        assert_eq!(subject.set_breakpoint_condition(1, None), Err(String::from("No breakpoint at 1")));
+        assert_eq!(subject.set_breakpoint_ignore_count(1, 5), Err(String::from("No breakpoint at 1")));
+    }
+
+    #[test]
+    fn step_back_restores_state() {
+        let code = parser::parse_str(String::from(",>+++<[->-<]"));
+        let mut subject = Interpreter::new(code);
+        subject.set_input(Box::new(&b"\x02"[..]));
+        subject.set_history_limit(100);
+
+        subject.run();
+        let steps = subject.steps();
+
+        while subject.step_back() {}
+
+        assert_eq!(subject.instruction_pointer, 0);
+        assert_eq!(subject.data_pointer, 0);
+        assert_eq!(subject.steps(), 0);
+        assert!(subject.tape.iter().all(|x| *x == 0));
+
+        assert_eq!(subject.run(), StopReason::Done);
+        assert_eq!(subject.steps(), steps);
+        assert_eq!(subject.tape[1], 1);
+    }
+
+    #[test]
+    fn step_back_does_not_repeat_output() {
+        let code = parser::parse_str(String::from("+.+.-."));
+        let mut subject = Interpreter::new(code);
+        let output = OutputBuffer::new();
+        subject.set_output(Box::new(output.clone()));
+        subject.set_history_limit(100);
+
+        subject.run();
+        for _ in 0..4 {
+            subject.step_back();
+        }
+        assert_eq!(subject.run(), StopReason::Done);
+        assert_eq!(output.take(), vec![1, 2, 1]);
+
+        // Output that differs from what was written the first time is written again.
+        subject.step_back();
+        subject.step_back();
+        subject.tape[0] = 7;
+        subject.run();
+        assert_eq!(output.take(), vec![6]);
+    }
+
+    #[test]
+    fn step_back_does_not_repeat_printed_output() {
+        let code = vec![
+            BrainfuckInstruction::Print(b"Hi".to_vec()),
+            BrainfuckInstruction::Add(33),
+            BrainfuckInstruction::Write,
+        ];
+        let mut subject = Interpreter::new(code);
+        let output = OutputBuffer::new();
+        subject.set_output(Box::new(output.clone()));
+        subject.set_history_limit(100);
+
+        subject.run();
+        for _ in 0..3 {
+            subject.step_back();
+        }
+        assert_eq!(subject.run(), StopReason::Done);
+        assert_eq!(output.take(), b"Hi!");
+    }
+
+    #[test]
+    fn manual_changes_forget_history() {
+        let code = parser::parse_str(String::from("+.+"));
+        let mut subject = Interpreter::new(code);
+        subject.set_output(Box::new(OutputBuffer::new()));
+        subject.set_history_limit(100);
+
+        subject.step();
+        subject.step();
+        subject.set(0, 5).unwrap();
+        assert_eq!(subject.history_len(), 0);
+        assert!(!subject.step_back());
+        assert_eq!(subject.tape[0], 5);
+
+        subject.step();
+        subject.jump(1).unwrap();
+        assert_eq!(subject.history_len(), 0);
+        subject.step();
+        subject.select(1).unwrap();
+        assert_eq!(subject.history_len(), 0);
+    }
+
+    #[test]
+    fn history_limit_forgets_oldest_steps() {
+        let code = parser::parse_str(String::from("++++++++++"));
+        let mut subject = Interpreter::new(code);
+        subject.set_history_limit(4);
+
+        subject.run();
+
+        assert_eq!(subject.history_len(), 4);
+        assert_eq!(subject.rewind(5), Err(String::from("History only goes back to 6")));
+        assert_eq!(subject.rewind(6), Ok(()));
+        assert_eq!(subject.tape[0], 6);
+        assert!(!subject.step_back());
+    }
+
+    #[test]
+    fn reverse_run_stops_at_breakpoints() {
+        let code = parser::parse_str(String::from("+++[>+<-]"));
+        let mut subject = Interpreter::new(code);
+        subject.set_history_limit(100);
+
+        subject.run();
+        subject.set_breakpoint(5).unwrap();
+        subject.set_breakpoint_condition(5, Some(expr::parse("*1 == 1").unwrap())).unwrap();
+
+        assert_eq!(subject.reverse_run(), StopReason::Breakpoint(5));
+        assert_eq!(subject.tape[0], 2);
+        assert_eq!(subject.tape[1], 1);
+        assert_eq!(subject.reverse_run(), StopReason::HistoryExhausted);
+        assert_eq!(subject.steps(), 0);
+    }
+}
@@ -1,5 +1,8 @@
 //! The Intermediate Representation used by bfkit to represent Brainfuck code.
 
+use std::convert::TryFrom;
+use std::fmt;
+
 /// Represents any of the eight standard Brainfuck instructions:
 ///
 /// * `+`
@@ -32,10 +35,106 @@ pub enum BrainfuckInstruction {
     Close,
     /// Set is an instruction that assigns a cell in the tape to some value.
     Set(u8),
-    /// ScanLeft represents the following sequence of Brainfuck instructions: `[<]`
-    ScanLeft,
-    /// ScanRight represents the following sequence of Brainfuck instructions: `[>]`
-    ScanRight,
+    /// ScanLeft represents a loop that moves left by some stride until it finds a zero cell: `[<]`, `[<<]`, etc.
+    ScanLeft(usize),
+    /// ScanRight represents a loop that moves right by some stride until it finds a zero cell: `[>]`, `[>>]`, etc.
+    ScanRight(usize),
+    /// Print is an instruction that writes a known sequence of bytes to the output.
+    Print(Vec<u8>),
+}
+
+/// The names of BrainfuckInstructions as printed by `ir_to_string`, indexed by opcode.
+pub const OPCODES: [&str; 12] = [
+    "add",
+    "sub",
+    "right",
+    "left",
+    "read",
+    "write",
+    "open",
+    "close",
+    "set",
+    "scan_left",
+    "scan_right",
+    "print",
+];
+
+impl BrainfuckInstruction {
+    /// Returns the opcode of the instruction, an index into `OPCODES`, along with its operand if it has a numeric one.
+    /// The bytes of a Print are not included.
+    pub fn opcode(&self) -> (u8, Option<u64>) {
+        match self {
+            BrainfuckInstruction::Add(count) => (0, Some(*count as u64)),
+            BrainfuckInstruction::Sub(count) => (1, Some(*count as u64)),
+            BrainfuckInstruction::Right(count) => (2, Some(*count as u64)),
+            BrainfuckInstruction::Left(count) => (3, Some(*count as u64)),
+            BrainfuckInstruction::Read => (4, None),
+            BrainfuckInstruction::Write => (5, None),
+            BrainfuckInstruction::Open => (6, None),
+            BrainfuckInstruction::Close => (7, None),
+            BrainfuckInstruction::Set(value) => (8, Some(*value as u64)),
+            BrainfuckInstruction::ScanLeft(stride) => (9, Some(*stride as u64)),
+            BrainfuckInstruction::ScanRight(stride) => (10, Some(*stride as u64)),
+            BrainfuckInstruction::Print(_) => (11, None),
+        }
+    }
+
+    /// Builds a BrainfuckInstruction from the parts returned by `opcode`.
+    ///
+    /// # Arguments
+    ///
+    /// * `opcode` - The opcode of the instruction.
+    /// * `operand` - The numeric operand, ignored by instructions without one.
+    /// * `bytes` - The bytes of a Print, ignored by other instructions.
+    pub fn from_opcode(opcode: u8, operand: u64, bytes: &[u8]) -> Result<Self, String> {
+        let byte = || u8::try_from(operand).map_err(|_| format!("Operand out of range: {}", operand));
+        let count = || usize::try_from(operand).map_err(|_| format!("Operand out of range: {}", operand));
+        let stride = || match count()? {
+            0 => Err(String::from("Scan stride must not be zero")),
+            stride => Ok(stride),
+        };
+
+        Ok(match opcode {
+            0 => BrainfuckInstruction::Add(byte()?),
+            1 => BrainfuckInstruction::Sub(byte()?),
+            2 => BrainfuckInstruction::Right(count()?),
+            3 => BrainfuckInstruction::Left(count()?),
+            4 => BrainfuckInstruction::Read,
+            5 => BrainfuckInstruction::Write,
+            6 => BrainfuckInstruction::Open,
+            7 => BrainfuckInstruction::Close,
+            8 => BrainfuckInstruction::Set(byte()?),
+            9 => BrainfuckInstruction::ScanLeft(stride()?),
+            10 => BrainfuckInstruction::ScanRight(stride()?),
+            11 => BrainfuckInstruction::Print(bytes.to_vec()),
+            _ => return Err(format!("Unknown opcode: {}", opcode)),
+        })
+    }
+}
+
+/// Returns true if instructions with some opcode have a numeric operand.
+///
+/// # Arguments
+///
+/// * `opcode` - The opcode to check.
+pub fn has_operand(opcode: u8) -> bool {
+    matches!(opcode, 0..=3 | 8..=10)
+}
+
+/// Formats a single BrainfuckInstruction the way `ir_to_string` prints it, without indentation.
+impl fmt::Display for BrainfuckInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BrainfuckInstruction::Print(bytes) => write!(f, "print \"{}\"", bytes.escape_ascii()),
+            _ => {
+                let (opcode, operand) = self.opcode();
+                match operand {
+                    Some(operand) => write!(f, "{} {}", OPCODES[opcode as usize], operand),
+                    None => write!(f, "{}", OPCODES[opcode as usize]),
+                }
+            }
+        }
+    }
 }
 
 /// Converts a sequence of BrainfuckInstructions to a string.
@@ -47,61 +146,17 @@ pub fn ir_to_string(ir: Vec<BrainfuckInstruction>) -> String {
     let mut result = String::new();
     let mut level = 0;
 
-    let indent = |result: &mut String, level: i32| {
+    for insn in ir {
+        if insn == BrainfuckInstruction::Close {
+            level -= 1;
+        }
         for _ in 0..level {
             result.push_str("    ");
         }
-    };
-
-    for insn in ir {
-        match insn {
-            BrainfuckInstruction::Add(count) => {
-                indent(&mut result, level);
-                result.push_str(&format!("add {}\n", count))
-            },
-            BrainfuckInstruction::Sub(count) => {
-                indent(&mut result, level);
-                result.push_str(&format!("sub {}\n", count))
-            },
-            BrainfuckInstruction::Right(count) => {
-                indent(&mut result, level);
-                result.push_str(&format!("right {}\n", count))
-            },
-            BrainfuckInstruction::Left(count) => {
-                indent(&mut result, level);
-                result.push_str(&format!("left {}\n", count))
-            },
-            BrainfuckInstruction::Read => {
-                indent(&mut result, level);
-                result.push_str("read\n")
-            },
-            BrainfuckInstruction::Write => {
-                indent(&mut result, level);
-                result.push_str("write\n")
-            },
-            BrainfuckInstruction::Open => {
-                indent(&mut result, level);
-                level += 1;
-                result.push_str("open\n")
-            },
-            BrainfuckInstruction::Close => {
-                level -= 1;
-                indent(&mut result, level);
-                result.push_str("close\n")
-            },
-            BrainfuckInstruction::Set(value) => {
-                indent(&mut result, level);
-                result.push_str(&format!("set {}\n", value))
-            },
-            BrainfuckInstruction::ScanRight => {
-                indent(&mut result, level);
-                result.push_str("scan_right\n")
-            },
-            BrainfuckInstruction::ScanLeft => {
-                indent(&mut result, level);
-                result.push_str("scan_left\n")
-            }
+        if insn == BrainfuckInstruction::Open {
+            level += 1;
         }
+        result.push_str(&format!("{}\n", insn));
     }
 
     result
@@ -115,13 +170,16 @@ mod tests {
 
     #[test]
     fn ir_to_string_works() {
-        let code = optimizer::optimize(parser::parse_str(String::from("++-[-],.[>++<-][<][>]")), 10);
+        let code = optimizer::optimize(parser::parse_str(String::from(",++>-<[-],.[>++<-][<][>]")), 10);
 
         let result = ir_to_string(code);
 
         let expected = vec![
+            "read",
             "add 2",
+            "right 1",
             "sub 1",
+            "left 1",
             "set 0",
             "read",
             "write",
@@ -131,12 +189,43 @@ mod tests {
             "    left 1",
             "    sub 1",
             "close",
-            "scan_left",
-            "scan_right",
+            "scan_left 1",
+            "scan_right 1",
         ]
             .iter()
             .fold(String::new(), |a, b| format!("{}\n{}", a, b));
 
         assert_eq!(result.trim(), expected.trim());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ir_to_string_escapes_print() {
+        let code = vec![BrainfuckInstruction::Print(b"Hi\n".to_vec())];
+
+        let result = ir_to_string(code);
+
+        assert_eq!(result, "print \"Hi\\n\"\n");
+    }
+
+    #[test]
+    fn ir_to_string_includes_scan_stride() {
+        let code = optimizer::optimize(parser::parse_str(String::from(",[>>][<<<]")), 10);
+
+        let result = ir_to_string(code);
+
+        assert_eq!(result, "read\nscan_right 2\nscan_left 3\n");
+    }
+
+    #[test]
+    fn from_opcode_rejects_invalid_operands() {
+        assert_eq!(BrainfuckInstruction::from_opcode(10, 2, &[]), Ok(BrainfuckInstruction::ScanRight(2)));
+        assert_eq!(
+            BrainfuckInstruction::from_opcode(9, 0, &[]),
+            Err(String::from("Scan stride must not be zero"))
+        );
+        assert_eq!(
+            BrainfuckInstruction::from_opcode(0, 256, &[]),
+            Err(String::from("Operand out of range: 256"))
+        );
+    }
+}
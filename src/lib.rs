@@ -2,7 +2,19 @@
 
 pub mod ir;
 pub mod compiler;
+pub mod coverage;
+pub mod dap;
+pub mod difftest;
+pub mod expr;
+pub mod gdbstub;
+pub mod generator;
+pub mod golden;
 pub mod interp;
+pub mod listing;
 pub mod optimizer;
 pub mod parser;
-pub mod repl;
\ No newline at end of file
+pub mod profile;
+pub mod repl;
+pub mod snapshot;
+pub mod trace;
+pub mod tui;
\ No newline at end of file
@@ -0,0 +1,253 @@
+//! Listings of source code, disassemblies of the IR and dumps of the tape around the current position of an
+//! Interpreter.
+
+use crate::interp::Interpreter;
+use crate::ir::BrainfuckInstruction;
+use crate::parser::Position;
+
+/// The number of tape cells shown on each row by `examine`.
+const EXAMINE_COLUMNS: usize = 8;
+
+/// Lists lines of source code, marking the current position with `=>` and a caret under its column.
+///
+/// # Arguments
+///
+/// * `source` - The Brainfuck source code.
+/// * `current` - The Position of the next instruction to execute, if any.
+/// * `center` - The line to center the listing on, starting at 1.
+/// * `count` - The maximum number of lines to list.
+pub fn list(source: &str, current: Option<Position>, center: usize, count: usize) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let first = center.saturating_sub(count / 2).max(1);
+    let last = first.saturating_add(count).min(lines.len() + 1);
+    let width = last.saturating_sub(1).to_string().len();
+    let mut result = String::new();
+
+    for number in first..last {
+        let line = lines[number - 1];
+        let is_current = current.is_some_and(|p| p.line == number);
+        let marker = if is_current { "=>" } else { "  " };
+        result.push_str(&format!("{} {:>width$}  {}\n", marker, number, line, width = width));
+
+        if let Some(position) = current.filter(|_| is_current) {
+            // Tabs are kept so that the caret lines up with the character above it.
+            let padding: String = line
+                .chars()
+                .take(position.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            result.push_str(&format!("{}  {}^\n", " ".repeat(width + 3), padding));
+        }
+    }
+
+    result
+}
+
+/// Disassembles the IR of an Interpreter, marking the instruction pointer with `=>` and breakpoints with `*`.
+/// Instructions are indented by loop nesting like `ir::ir_to_string`.
+///
+/// # Arguments
+///
+/// * `interp` - The Interpreter whose code is disassembled.
+/// * `center` - The code address to center the disassembly on.
+/// * `count` - The maximum number of instructions to disassemble.
+pub fn disassemble(interp: &Interpreter, center: usize, count: usize) -> String {
+    let code = interp.code();
+    let first = center.saturating_sub(count / 2);
+    let last = first.saturating_add(count).min(code.len());
+    let width = code.len().saturating_sub(1).to_string().len();
+    let mut result = String::new();
+
+    let mut level = code[..first.min(code.len())]
+        .iter()
+        .map(|insn| match insn {
+            BrainfuckInstruction::Open => 1,
+            BrainfuckInstruction::Close => -1,
+            _ => 0,
+        })
+        .sum::<i32>();
+
+    for (address, insn) in code.iter().enumerate().take(last).skip(first) {
+        if *insn == BrainfuckInstruction::Close {
+            level -= 1;
+        }
+
+        let marker = if address == interp.instruction_pointer() { "=>" } else { "  " };
+        let breakpoint = if interp.breakpoint(address).is_some() { "*" } else { " " };
+        result.push_str(&format!(
+            "{} {} {:>width$}  {}{}\n",
+            marker,
+            breakpoint,
+            address,
+            "    ".repeat(level as usize),
+            insn,
+            width = width
+        ));
+
+        if *insn == BrainfuckInstruction::Open {
+            level += 1;
+        }
+    }
+
+    result
+}
+
+/// Dumps a range of tape cells in rows of decimal, hex and ASCII columns.
+/// The cell under the data pointer is marked with `>` and cells that differ from `previous` with `*`.
+/// Rows of zeroes at the end of the range are summarized in a single line.
+///
+/// # Arguments
+///
+/// * `tape` - The tape to dump.
+/// * `start` - The address of the first cell to dump.
+/// * `count` - The number of cells to dump.
+/// * `data_pointer` - The current data pointer.
+/// * `previous` - The tape as it was at the last stop.
+pub fn examine(tape: &[u8], start: usize, count: usize, data_pointer: usize, previous: &[u8]) -> String {
+    let end = start.saturating_add(count).min(tape.len());
+    let changed = |address: usize| previous.get(address) != tape.get(address);
+    let mut result = String::new();
+
+    // Rows are only trimmed if nothing in them is worth pointing out.
+    let last = (start..end)
+        .rev()
+        .find(|address| tape[*address] != 0 || *address == data_pointer || changed(*address))
+        .map_or(start.min(end), |address| address + 1);
+    let trimmed = if last == start {
+        start
+    } else {
+        (start + (last - start).div_ceil(EXAMINE_COLUMNS) * EXAMINE_COLUMNS).min(end)
+    };
+
+    let width = end.saturating_sub(1).to_string().len();
+    for row in (start..trimmed).step_by(EXAMINE_COLUMNS) {
+        let cells = row..(row + EXAMINE_COLUMNS).min(trimmed);
+        let mut decimal = String::new();
+        let mut hex = String::new();
+        let mut ascii = String::new();
+
+        for address in cells {
+            let value = tape[address];
+            decimal.push(if address == data_pointer { '>' } else { ' ' });
+            decimal.push_str(&format!("{:>3}", value));
+            decimal.push(if changed(address) { '*' } else { ' ' });
+            hex.push_str(&format!(" {:02x}", value));
+            ascii.push(if value.is_ascii_graphic() || value == b' ' { value as char } else { '.' });
+        }
+
+        result.push_str(&format!(
+            "{:>width$}: {:<w1$}  {:<w2$}  |{}|\n",
+            row,
+            decimal,
+            hex,
+            ascii,
+            width = width,
+            w1 = EXAMINE_COLUMNS * 5,
+            w2 = EXAMINE_COLUMNS * 3
+        ));
+    }
+
+    if trimmed < end {
+        result.push_str(&format!("{:>width$}: cells {}-{} are zero\n", trimmed, trimmed, end - 1, width = width));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer;
+    use crate::parser;
+
+    #[test]
+    fn list_marks_the_current_position() {
+        let source = "copy\n\t[->+<]\nend .";
+        let positions = parser::positions(source);
+
+        let result = list(source, Some(positions[2]), 2, 10);
+
+        assert_eq!(
+            result,
+            [
+                "   1  copy",
+                "=> 2  \t[->+<]",
+                "      \t  ^",
+                "   3  end .",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn list_shows_a_window_of_lines() {
+        let source = (1..=30).map(|n| format!("line {}\n", n)).collect::<String>();
+
+        let result = list(&source, None, 20, 4);
+
+        assert_eq!(result, "   18  line 18\n   19  line 19\n   20  line 20\n   21  line 21\n");
+    }
+
+    #[test]
+    fn disassemble_shows_breakpoints_and_nesting() {
+        let code = optimizer::optimize(parser::parse_str(String::from(",[>++[>+<-]<-].")), 10);
+        let mut interp = Interpreter::new(code);
+        interp.set_breakpoint(6).unwrap();
+        interp.jump(5).unwrap();
+
+        let result = disassemble(&interp, 5, 6);
+
+        assert_eq!(
+            result,
+            [
+                "      2      right 1",
+                "      3      add 2",
+                "      4      open",
+                "=>    5          right 1",
+                "   *  6          add 1",
+                "      7          left 1",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn list_and_disassemble_accept_centers_past_the_end() {
+        let interp = Interpreter::new(parser::parse_str(String::from("+.")));
+
+        assert_eq!(list("+.\n", None, usize::MAX, usize::MAX), "");
+        assert_eq!(disassemble(&interp, usize::MAX, usize::MAX), "");
+        assert_eq!(disassemble(&interp, usize::MAX, 10), "");
+    }
+
+    #[test]
+    fn examine_marks_pointer_and_changes_and_trims_zeroes() {
+        let mut tape = vec![0u8; 64];
+        tape[8..12].copy_from_slice(b"Hi!\n");
+        let previous = tape.clone();
+        tape[9] = b'o';
+
+        let result = examine(&tape, 8, 32, 11, &previous);
+
+        assert_eq!(
+            result,
+            [
+                " 8:   72  111*  33 > 10    0    0    0    0    48 6f 21 0a 00 00 00 00  |Ho!.....|",
+                "16: cells 16-39 are zero",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn examine_stops_at_the_end_of_the_tape() {
+        let tape = vec![1u8; 10];
+
+        let result = examine(&tape, 6, 32, 0, &tape);
+
+        assert_eq!(result, "6:    1    1    1    1                        01 01 01 01              |....|\n");
+    }
+}
@@ -1,16 +1,38 @@
-use bfkit::{compiler, parser, optimizer, repl, ir};
-use clap::{crate_authors, crate_description, crate_name, App, Arg};
-use std::fs;
+use bfkit::{compiler, coverage, dap, difftest, gdbstub, generator, golden, parser, optimizer, profile, repl, ir, trace, tui};
+use bfkit::interp::{Interpreter, OutputBuffer};
+use clap::{crate_authors, crate_description, crate_name, App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, Write};
+use std::net::TcpListener;
+use std::panic;
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn main() {
     let matches = App::new(crate_name!())
         .about(crate_description!())
         .author(crate_authors!())
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("interactive")
                 .short("i")
                 .help("Run an interactive REPL"),
         )
+        .arg(
+            Arg::with_name("script")
+                .short("x")
+                .takes_value(true)
+                .value_name("SCRIPT")
+                .conflicts_with("interactive")
+                .help("Run a script of debugger commands and print a transcript of the session"),
+        )
+        .arg(
+            Arg::with_name("input-file")
+                .long("input-file")
+                .takes_value(true)
+                .value_name("INPUT")
+                .help("A file or pipe the program reads its input from in the debugger"),
+        )
         .arg(
             Arg::with_name("output-type")
                 .short("t")
@@ -33,12 +55,424 @@ fn main() {
                 .required(true)
                 .help("Brainfuck source file to be compiled or executed"),
         )
+        .subcommand(
+            SubCommand::with_name("difftest")
+                .about("Checks that optimization does not change the behavior of programs")
+                .arg(
+                    Arg::with_name("random")
+                        .long("random")
+                        .takes_value(true)
+                        .value_name("COUNT")
+                        .default_value("0")
+                        .help("The number of randomly generated programs to check"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("The seed for generating random programs"),
+                )
+                .arg(
+                    Arg::with_name("length")
+                        .long("length")
+                        .takes_value(true)
+                        .default_value("100")
+                        .help("The approximate length of randomly generated programs"),
+                )
+                .arg(
+                    Arg::with_name("max-depth")
+                        .long("max-depth")
+                        .takes_value(true)
+                        .default_value("3")
+                        .help("The maximum loop nesting depth of randomly generated programs"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .takes_value(true)
+                        .default_value("")
+                        .help("The input given to every program"),
+                )
+                .arg(
+                    Arg::with_name("max-steps")
+                        .long("max-steps")
+                        .takes_value(true)
+                        .default_value("10000000")
+                        .help("The maximum number of instructions to execute per run"),
+                )
+                .arg(
+                    Arg::with_name("files")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .multiple(true)
+                        .help("Brainfuck source files to be checked"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("Runs golden input/output tests for the Brainfuck programs in some directories")
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .takes_value(true)
+                        .possible_values(&["interpreter", "c", "all"])
+                        .default_value("all")
+                        .help("The backends to run the programs with"),
+                )
+                .arg(
+                    Arg::with_name("cc")
+                        .long("cc")
+                        .takes_value(true)
+                        .default_value("cc")
+                        .help("The C compiler used by the c backend"),
+                )
+                .arg(
+                    Arg::with_name("max-steps")
+                        .long("max-steps")
+                        .takes_value(true)
+                        .default_value("10000000000")
+                        .help("The maximum number of instructions the interpreter may execute per program"),
+                )
+                .arg(
+                    Arg::with_name("directories")
+                        .takes_value(true)
+                        .value_name("DIRECTORY")
+                        .multiple(true)
+                        .required(true)
+                        .help("Directories containing .bf files with matching .out or .head files"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("coverage")
+                .about("Runs a program in the interpreter and reports which parts of it were executed")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["lcov", "cobertura"])
+                        .default_value("lcov")
+                        .help("The format of the coverage report"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .takes_value(true)
+                        .value_name("OUTPUT")
+                        .help("The file the coverage report is written to"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("The input given to the program; each one is a separate run"),
+                )
+                .arg(
+                    Arg::with_name("input-file")
+                        .long("input-file")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("A file whose contents are given to the program; each one is a separate run"),
+                )
+                .arg(
+                    Arg::with_name("max-steps")
+                        .long("max-steps")
+                        .takes_value(true)
+                        .default_value("10000000000")
+                        .help("The maximum number of instructions to execute per run"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Brainfuck source file to be measured"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("profile")
+                .about("Runs a program in the interpreter and reports its hottest loops")
+                .arg(
+                    Arg::with_name("optimize")
+                        .long("optimize")
+                        .help("Optimize the program before running it, locating loops by code address"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .takes_value(true)
+                        .default_value("")
+                        .help("The input given to the program"),
+                )
+                .arg(
+                    Arg::with_name("max-steps")
+                        .long("max-steps")
+                        .takes_value(true)
+                        .default_value("10000000000")
+                        .help("The maximum number of instructions to execute"),
+                )
+                .arg(
+                    Arg::with_name("top")
+                        .long("top")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("The number of loops to report"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Brainfuck source file to be profiled"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("trace")
+                .about("Records, filters and summarizes execution traces")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("record")
+                        .about("Runs a program in the interpreter and records a trace of its execution")
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .takes_value(true)
+                                .value_name("TRACE")
+                                .required(true)
+                                .help("The trace file to be written"),
+                        )
+                        .arg(
+                            Arg::with_name("format")
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(&["binary", "json"])
+                                .default_value("binary")
+                                .help("The encoding of the trace"),
+                        )
+                        .arg(
+                            Arg::with_name("every")
+                                .long("every")
+                                .takes_value(true)
+                                .default_value("1")
+                                .help("Only record every Nth step"),
+                        )
+                        .arg(
+                            Arg::with_name("optimize")
+                                .long("optimize")
+                                .help("Optimize the program before running it"),
+                        )
+                        .arg(
+                            Arg::with_name("input")
+                                .long("input")
+                                .takes_value(true)
+                                .default_value("")
+                                .help("The input given to the program"),
+                        )
+                        .arg(
+                            Arg::with_name("max-steps")
+                                .long("max-steps")
+                                .takes_value(true)
+                                .default_value("10000000")
+                                .help("The maximum number of instructions to execute"),
+                        )
+                        .arg(
+                            Arg::with_name("file")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .required(true)
+                                .help("Brainfuck source file to be traced"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("filter")
+                        .about("Prints the records of a trace that match some conditions as JSON lines")
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .takes_value(true)
+                                .value_name("STEP")
+                                .help("Only print records from this step onwards"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .long("to")
+                                .takes_value(true)
+                                .value_name("STEP")
+                                .help("Only print records up to this step"),
+                        )
+                        .arg(
+                            Arg::with_name("ip")
+                                .long("ip")
+                                .takes_value(true)
+                                .value_name("ADDRESS")
+                                .help("Only print records of the instruction at this code address"),
+                        )
+                        .arg(
+                            Arg::with_name("dp")
+                                .long("dp")
+                                .takes_value(true)
+                                .value_name("ADDRESS")
+                                .help("Only print records where the data pointer was at this tape address"),
+                        )
+                        .arg(
+                            Arg::with_name("op")
+                                .long("op")
+                                .takes_value(true)
+                                .help("Only print records of instructions with this opcode, such as add or scan_left"),
+                        )
+                        .arg(
+                            Arg::with_name("io")
+                                .long("io")
+                                .help("Only print records that read or wrote some bytes"),
+                        )
+                        .arg(
+                            Arg::with_name("trace")
+                                .takes_value(true)
+                                .value_name("TRACE")
+                                .required(true)
+                                .help("The trace file to be read"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("summary")
+                        .about("Prints statistics about a trace")
+                        .arg(
+                            Arg::with_name("trace")
+                                .takes_value(true)
+                                .value_name("TRACE")
+                                .required(true)
+                                .help("The trace file to be read"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("dap")
+                .about("Runs a Debug Adapter Protocol server over stdin and stdout"),
+        )
+        .subcommand(
+            SubCommand::with_name("gdbserver")
+                .about("Runs a program under a GDB remote protocol stub, on stdin and stdout or a TCP port")
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .takes_value(true)
+                        .help("The local TCP port to listen on for a connection from gdb"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .takes_value(true)
+                        .default_value("")
+                        .help("The input given to the program"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Brainfuck source file to be debugged"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tui")
+                .about("Debugs a program in a full-screen terminal interface")
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .takes_value(true)
+                        .conflicts_with("input-file")
+                        .help("The input given to the program"),
+                )
+                .arg(
+                    Arg::with_name("input-file")
+                        .long("input-file")
+                        .takes_value(true)
+                        .help("A file whose contents are given to the program"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Brainfuck source file to be debugged"),
+                ),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("difftest") {
+        exit(run_difftest(matches));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("test") {
+        exit(run_golden(matches));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("coverage") {
+        exit(run_coverage(matches));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("profile") {
+        exit(run_profile(matches));
+    }
+
+    if matches.subcommand_matches("dap").is_some() {
+        if let Err(e) = dap::serve(io::BufReader::new(io::stdin()), io::stdout()) {
+            eprintln!("{}", e);
+            exit(2);
+        }
+        exit(0);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("gdbserver") {
+        if let Err(e) = run_gdbserver(matches) {
+            eprintln!("{}", e);
+            exit(2);
+        }
+        exit(0);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("tui") {
+        exit(run_tui(matches));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("trace") {
+        let result = match matches.subcommand() {
+            ("record", Some(matches)) => run_trace_record(matches),
+            ("filter", Some(matches)) => run_trace_filter(matches),
+            ("summary", Some(matches)) => run_trace_summary(matches),
+            _ => unreachable!(),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(2);
+        }
+        exit(0);
+    }
+
     let source = fs::read_to_string(matches.value_of("file").unwrap()).unwrap();
 
-    if matches.is_present("interactive") {
-        repl::repl(source);
+    let input: Box<dyn io::Read> = match matches.value_of("input-file") {
+        Some(path) => Box::new(File::open(path).unwrap_or_else(|e| {
+            eprintln!("Failed to open {}: {}", path, e);
+            exit(2);
+        })),
+        None => Box::new(io::empty()),
+    };
+
+    if let Some(path) = matches.value_of("script") {
+        let commands = File::open(path).map(io::BufReader::new).unwrap_or_else(|e| {
+            eprintln!("Failed to open {}: {}", path, e);
+            exit(2);
+        });
+        if let Err(e) = repl::script(source, input, commands, io::stdout()) {
+            eprintln!("{}: {}", path, e);
+            exit(1);
+        }
+    } else if matches.is_present("interactive") {
+        repl::repl(source, input);
     } else {
         let code = optimizer::optimize(parser::parse_str(source), 10);
 
@@ -54,4 +488,285 @@ fn main() {
             println!("{}", result);
         }
     }
-}
\ No newline at end of file
+}
+
+fn number<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> T {
+    let value = matches.value_of(name).unwrap();
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid value for --{}: {}", name, value);
+        exit(2);
+    })
+}
+
+fn optional_number<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    if matches.is_present(name) {
+        Some(number(matches, name))
+    } else {
+        None
+    }
+}
+
+fn run_difftest(matches: &ArgMatches) -> i32 {
+    let random: u64 = number(matches, "random");
+    let seed: u64 = number(matches, "seed");
+    let max_steps: u64 = number(matches, "max-steps");
+    let input = matches.value_of("input").unwrap().as_bytes();
+
+    // Crashes are reported as divergences, so the default panic messages would only repeat them.
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failures = 0;
+
+    for file in matches.values_of("files").into_iter().flatten() {
+        let source = fs::read_to_string(file).unwrap();
+        match difftest::check(&source, input, max_steps) {
+            Ok(()) => println!("{}: OK", file),
+            Err(divergence) => {
+                println!("{}: {}", file, divergence);
+                failures += 1;
+            }
+        }
+    }
+
+    let config = generator::Config {
+        length: number(matches, "length"),
+        max_depth: number(matches, "max-depth"),
+        ..generator::Config::default()
+    };
+    let mut generator = generator::Generator::with_config(seed, config);
+    for index in 0..random {
+        let source = generator.generate();
+        if let Err(divergence) = difftest::check(&source, input, max_steps) {
+            println!("Random program {} (seed {}): {}\n{}", index, seed, divergence, source);
+            failures += 1;
+        }
+    }
+    if random > 0 {
+        println!("Checked {} random programs", random);
+    }
+
+    if failures > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+fn run_golden(matches: &ArgMatches) -> i32 {
+    let backends = match matches.value_of("backend").unwrap() {
+        "interpreter" => vec![golden::Backend::Interpreter],
+        "c" => vec![golden::Backend::C],
+        _ => vec![golden::Backend::Interpreter, golden::Backend::C],
+    };
+    let options = golden::Options {
+        max_steps: number(matches, "max-steps"),
+        compiler: matches.value_of("cc").unwrap().to_string(),
+        ..golden::Options::default()
+    };
+
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for directory in matches.values_of("directories").unwrap() {
+        let cases = match golden::discover(directory.as_ref()) {
+            Ok(cases) => cases,
+            Err(e) => {
+                eprintln!("{}: {}", directory, e);
+                return 2;
+            }
+        };
+
+        for case in &cases {
+            for backend in &backends {
+                match golden::check(case, *backend, &options) {
+                    Ok(()) => {
+                        println!("{} ({}): OK", case.path.display(), backend);
+                        passed += 1;
+                    }
+                    Err(e) => {
+                        println!("{} ({}): FAILED\n{}", case.path.display(), backend, e);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+fn run_coverage(matches: &ArgMatches) -> i32 {
+    let path = matches.value_of("file").unwrap();
+    let source = fs::read_to_string(path).unwrap();
+    let max_steps: u64 = number(matches, "max-steps");
+
+    let mut inputs: Vec<Vec<u8>> = matches
+        .values_of("input")
+        .into_iter()
+        .flatten()
+        .map(|input| input.as_bytes().to_vec())
+        .collect();
+    for file in matches.values_of("input-file").into_iter().flatten() {
+        inputs.push(fs::read(file).unwrap());
+    }
+    if inputs.is_empty() {
+        inputs.push(Vec::new());
+    }
+
+    let mut coverage = coverage::Coverage::new(&source);
+    for input in inputs {
+        let mut interp = Interpreter::new(coverage.code().to_vec());
+        interp.set_input(Box::new(Cursor::new(input)));
+        interp.set_output(Box::new(io::sink()));
+        interp.set_step_budget(Some(max_steps));
+        interp.set_profiling(true);
+        interp.run();
+        if !interp.is_done() {
+            eprintln!("Stopped after {} steps", interp.steps());
+        }
+        coverage.add(interp.profile().unwrap());
+    }
+
+    let result = match matches.value_of("format").unwrap() {
+        "cobertura" => {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            coverage.cobertura(path, timestamp)
+        }
+        _ => coverage.lcov(path),
+    };
+
+    if let Some(output) = matches.value_of("output") {
+        fs::write(output, result).unwrap();
+    } else {
+        print!("{}", result);
+    }
+    0
+}
+
+fn run_gdbserver(matches: &ArgMatches) -> io::Result<()> {
+    let source = fs::read_to_string(matches.value_of("file").unwrap())?;
+    let code = parser::parse_str(source);
+    if let Err(e) = parser::check_brackets(&code) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+
+    let output = OutputBuffer::new();
+    let mut interp = Interpreter::new(code);
+    interp.set_input(Box::new(Cursor::new(matches.value_of("input").unwrap().as_bytes().to_vec())));
+    interp.set_output(Box::new(output.clone()));
+
+    match optional_number::<u16>(matches, "port") {
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            eprintln!("Listening on port {}", listener.local_addr()?.port());
+            let (stream, _) = listener.accept()?;
+            gdbstub::serve(&mut interp, &output, stream.try_clone()?, stream)
+        }
+        None => gdbstub::serve(&mut interp, &output, io::stdin(), io::stdout()),
+    }
+}
+
+fn run_tui(matches: &ArgMatches) -> i32 {
+    let source = fs::read_to_string(matches.value_of("file").unwrap()).unwrap();
+    let input = match matches.value_of("input-file") {
+        Some(file) => fs::read(file).unwrap(),
+        None => matches.value_of("input").unwrap_or("").as_bytes().to_vec(),
+    };
+
+    match tui::tui(source, input) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            2
+        }
+    }
+}
+
+fn run_profile(matches: &ArgMatches) -> i32 {
+    let source = fs::read_to_string(matches.value_of("file").unwrap()).unwrap();
+    let code = parser::parse_str(source.clone());
+    let (code, positions) = if matches.is_present("optimize") {
+        (optimizer::optimize(code, 10), None)
+    } else {
+        (code, Some(parser::positions(&source)))
+    };
+
+    let mut interp = Interpreter::new(code.clone());
+    interp.set_input(Box::new(Cursor::new(matches.value_of("input").unwrap().as_bytes().to_vec())));
+    interp.set_output(Box::new(io::sink()));
+    interp.set_step_budget(Some(number(matches, "max-steps")));
+    interp.set_profiling(true);
+    interp.run();
+
+    if !interp.is_done() {
+        println!("Stopped after {} steps", interp.steps());
+    }
+    print!(
+        "{}",
+        profile::report(interp.profile().unwrap(), &code, positions.as_deref(), number(matches, "top"))
+    );
+    0
+}
+
+fn run_trace_record(matches: &ArgMatches) -> io::Result<()> {
+    let format = match matches.value_of("format").unwrap() {
+        "json" => trace::Format::Json,
+        _ => trace::Format::Binary,
+    };
+    let source = fs::read_to_string(matches.value_of("file").unwrap())?;
+    let mut code = parser::parse_str(source);
+    if matches.is_present("optimize") {
+        code = optimizer::optimize(code, 10);
+    }
+
+    let output = BufWriter::new(File::create(matches.value_of("output").unwrap())?);
+    let tracer = trace::Tracer::new(Box::new(output), format, number(matches, "every"))?;
+
+    let mut interp = Interpreter::new(code);
+    interp.set_input(Box::new(Cursor::new(matches.value_of("input").unwrap().as_bytes().to_vec())));
+    interp.set_step_budget(Some(number(matches, "max-steps")));
+    interp.set_tracer(Some(tracer));
+    interp.run();
+    interp.take_tracer().unwrap().flush()?;
+
+    if !interp.is_done() {
+        eprintln!("Stopped after {} steps", interp.steps());
+    }
+    Ok(())
+}
+
+fn run_trace_filter(matches: &ArgMatches) -> io::Result<()> {
+    let filter = trace::Filter {
+        from: optional_number(matches, "from"),
+        to: optional_number(matches, "to"),
+        instruction_pointer: optional_number(matches, "ip"),
+        data_pointer: optional_number(matches, "dp"),
+        op: matches.value_of("op").map(String::from),
+        io: matches.is_present("io"),
+    };
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for record in trace::TraceReader::new(File::open(matches.value_of("trace").unwrap())?)? {
+        let record = record?;
+        if filter.matches(&record) {
+            writeln!(stdout, "{}", trace::to_json(&record))?;
+        }
+    }
+    Ok(())
+}
+
+fn run_trace_summary(matches: &ArgMatches) -> io::Result<()> {
+    let mut summary = trace::Summary::default();
+    for record in trace::TraceReader::new(File::open(matches.value_of("trace").unwrap())?)? {
+        summary.add(&record?);
+    }
+    print!("{}", summary);
+    Ok(())
+}
@@ -13,8 +13,10 @@ pub fn optimize(ir: Vec<BrainfuckInstruction>, max_passes: u32) -> Vec<Brainfuck
     let opts: Vec<Optimization> = vec![
         dead_code_removal,
         contraction,
+        cancellation,
         clear_loop_removal,
         scan_loop_removal,
+        prefix_evaluation,
     ];
 
     let mut current = ir;
@@ -42,7 +44,7 @@ pub fn optimize(ir: Vec<BrainfuckInstruction>, max_passes: u32) -> Vec<Brainfuck
 type Optimization = fn(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction>;
 
 fn dead_code_removal(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction> {
-    if let BrainfuckInstruction::Open = ir[0] {
+    if let Some(BrainfuckInstruction::Open) = ir.first() {
         let mut index = 1;
         let mut level = 1;
 
@@ -62,7 +64,19 @@ fn dead_code_removal(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction>
 }
 
 fn clear_loop_removal(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction> {
-    fn match_clear(ir: &Vec<BrainfuckInstruction>, index: usize) -> bool {
+    // Repeatedly adding or subtracting an odd number reaches zero from any starting value
+    // under wrapping arithmetic. An even number only reaches zero from a multiple of the
+    // largest power of two dividing it, so we need to know something about the cell.
+    fn clears(n: u8, known: Option<u8>) -> bool {
+        match known {
+            _ if n % 2 == 1 => true,
+            Some(value) if n == 0 => value == 0,
+            Some(value) => value % (1 << n.trailing_zeros()) == 0,
+            None => false,
+        }
+    }
+
+    fn match_clear(ir: &[BrainfuckInstruction], index: usize, known: Option<u8>) -> bool {
         if index + 2 >= ir.len() {
             return false;
         }
@@ -70,9 +84,9 @@ fn clear_loop_removal(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction
         match (&ir[index], &ir[index + 1], &ir[index + 2]) {
             (
                 BrainfuckInstruction::Open,
-                BrainfuckInstruction::Sub(n),
+                BrainfuckInstruction::Add(n) | BrainfuckInstruction::Sub(n),
                 BrainfuckInstruction::Close,
-            ) if *n == 1 => true,
+            ) => clears(*n, known),
             _ => false,
         }
     }
@@ -81,9 +95,26 @@ fn clear_loop_removal(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction
 
     let mut index = 0;
     while index < ir.len() {
-        if match_clear(&ir, index) {
-            result.push(BrainfuckInstruction::Set(0));
+        let known = match result.last() {
+            Some(BrainfuckInstruction::Set(value)) => Some(*value),
+            Some(BrainfuckInstruction::Close) => Some(0),
+            _ => None,
+        };
+
+        if match_clear(&ir, index, known) {
             index += 3;
+
+            let mut value = 0u8;
+            while index < ir.len() {
+                match &ir[index] {
+                    BrainfuckInstruction::Add(n) => value = value.wrapping_add(*n),
+                    BrainfuckInstruction::Sub(n) => value = value.wrapping_sub(*n),
+                    _ => break,
+                }
+                index += 1;
+            }
+
+            result.push(BrainfuckInstruction::Set(value));
         } else {
             result.push(ir[index].clone());
             index += 1;
@@ -131,8 +162,64 @@ macro_rules! generate_contraction {
 
 generate_contraction!(Add, Sub, Right, Left);
 
+fn cancellation(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction> {
+    let mut result = Vec::new();
+
+    let mut index = 0;
+    while index < ir.len() {
+        match &ir[index] {
+            BrainfuckInstruction::Add(_) | BrainfuckInstruction::Sub(_) | BrainfuckInstruction::Set(_) => {
+                let mut set = None;
+                let mut delta = 0u8;
+                while index < ir.len() {
+                    match &ir[index] {
+                        BrainfuckInstruction::Add(n) => delta = delta.wrapping_add(*n),
+                        BrainfuckInstruction::Sub(n) => delta = delta.wrapping_sub(*n),
+                        BrainfuckInstruction::Set(n) => {
+                            set = Some(*n);
+                            delta = 0;
+                        }
+                        _ => break,
+                    }
+                    index += 1;
+                }
+
+                match set {
+                    Some(value) => result.push(BrainfuckInstruction::Set(value.wrapping_add(delta))),
+                    None if delta == 0 => {}
+                    None if delta <= 128 => result.push(BrainfuckInstruction::Add(delta)),
+                    None => result.push(BrainfuckInstruction::Sub(delta.wrapping_neg())),
+                }
+            }
+            BrainfuckInstruction::Right(_) | BrainfuckInstruction::Left(_) => {
+                let mut offset = 0isize;
+                while index < ir.len() {
+                    match &ir[index] {
+                        BrainfuckInstruction::Right(n) => offset += *n as isize,
+                        BrainfuckInstruction::Left(n) => offset -= *n as isize,
+                        _ => break,
+                    }
+                    index += 1;
+                }
+
+                if offset > 0 {
+                    result.push(BrainfuckInstruction::Right(offset as usize));
+                } else if offset < 0 {
+                    result.push(BrainfuckInstruction::Left(-offset as usize));
+                }
+            }
+            _ => {
+                result.push(ir[index].clone());
+                index += 1;
+            }
+        }
+    }
+
+    result
+}
+
 fn scan_loop_removal(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction> {
-    fn match_scan_loop(ir: &Vec<BrainfuckInstruction>, index: usize) -> bool {
+    fn match_scan_loop(ir: &[BrainfuckInstruction], index: usize) -> bool {
         if index + 2 >= ir.len() {
             return false;
         }
@@ -142,12 +229,12 @@ fn scan_loop_removal(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction>
                 BrainfuckInstruction::Open,
                 BrainfuckInstruction::Left(n),
                 BrainfuckInstruction::Close,
-            ) if *n == 1 => true,
+            ) if *n > 0 => true,
             (
                 BrainfuckInstruction::Open,
                 BrainfuckInstruction::Right(n),
                 BrainfuckInstruction::Close,
-            ) if *n == 1 => true,
+            ) if *n > 0 => true,
             _ => false,
         }
     }
@@ -157,10 +244,10 @@ fn scan_loop_removal(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction>
     let mut index = 0;
     while index < ir.len() {
         if match_scan_loop(&ir, index) {
-            if let BrainfuckInstruction::Left(_) = &ir[index + 1] {
-                result.push(BrainfuckInstruction::ScanLeft);
-            } else if let BrainfuckInstruction::Right(_) = &ir[index + 1] {
-                result.push(BrainfuckInstruction::ScanRight);
+            if let BrainfuckInstruction::Left(stride) = &ir[index + 1] {
+                result.push(BrainfuckInstruction::ScanLeft(*stride));
+            } else if let BrainfuckInstruction::Right(stride) = &ir[index + 1] {
+                result.push(BrainfuckInstruction::ScanRight(*stride));
             } else {
                 unreachable!();
             }
@@ -175,6 +262,164 @@ fn scan_loop_removal(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction>
     result
 }
 
+/// The maximum number of instructions `prefix_evaluation` will execute before giving up.
+const PREFIX_EVALUATION_BUDGET: usize = 100_000;
+
+fn prefix_evaluation(ir: Vec<BrainfuckInstruction>) -> Vec<BrainfuckInstruction> {
+    fn compute_jump_table(ir: &[BrainfuckInstruction]) -> Option<Vec<usize>> {
+        let mut table = vec![0; ir.len()];
+        let mut stack = Vec::new();
+
+        for (index, insn) in ir.iter().enumerate() {
+            match insn {
+                BrainfuckInstruction::Open => stack.push(index),
+                BrainfuckInstruction::Close => {
+                    let other = stack.pop()?;
+                    table[index] = other;
+                    table[other] = index;
+                }
+                _ => {}
+            }
+        }
+
+        if stack.is_empty() {
+            Some(table)
+        } else {
+            None
+        }
+    }
+
+    fn move_to(result: &mut Vec<BrainfuckInstruction>, from: usize, to: usize) {
+        if to > from {
+            result.push(BrainfuckInstruction::Right(to - from));
+        } else if to < from {
+            result.push(BrainfuckInstruction::Left(from - to));
+        }
+    }
+
+    let jump_table = match compute_jump_table(&ir) {
+        Some(table) => table,
+        None => return ir,
+    };
+
+    let mut tape = vec![0u8; 1];
+    let mut data_pointer = 0;
+    let mut output = Vec::new();
+    let mut steps = 0;
+
+    // The state of the machine just before the outermost loop currently being executed.
+    // If evaluation has to stop inside of a loop, we roll back to this point since there
+    // is no way to resume execution in the middle of a loop body.
+    let mut checkpoint = (0, tape.clone(), data_pointer, output.len());
+    let mut level = 0;
+
+    let mut index = 0;
+    let stop = loop {
+        if index >= ir.len() {
+            break index;
+        }
+
+        if steps >= PREFIX_EVALUATION_BUDGET {
+            break checkpoint.0;
+        }
+        steps += 1;
+
+        match &ir[index] {
+            BrainfuckInstruction::Add(count) => {
+                tape[data_pointer] = tape[data_pointer].wrapping_add(*count)
+            }
+            BrainfuckInstruction::Sub(count) => {
+                tape[data_pointer] = tape[data_pointer].wrapping_sub(*count)
+            }
+            BrainfuckInstruction::Right(count) => {
+                data_pointer += count;
+                if data_pointer >= tape.len() {
+                    tape.resize(data_pointer + 1, 0);
+                }
+            }
+            BrainfuckInstruction::Left(count) => {
+                if *count > data_pointer {
+                    break checkpoint.0;
+                }
+                data_pointer -= count;
+            }
+            BrainfuckInstruction::Read => break checkpoint.0,
+            BrainfuckInstruction::Write => output.push(tape[data_pointer]),
+            BrainfuckInstruction::Open => {
+                if tape[data_pointer] == 0 {
+                    index = jump_table[index];
+                } else {
+                    if level == 0 {
+                        checkpoint = (index, tape.clone(), data_pointer, output.len());
+                    }
+                    level += 1;
+                }
+            }
+            BrainfuckInstruction::Close => {
+                if tape[data_pointer] != 0 {
+                    index = jump_table[index];
+                } else {
+                    level -= 1;
+                }
+            }
+            BrainfuckInstruction::Set(value) => tape[data_pointer] = *value,
+            BrainfuckInstruction::ScanLeft(stride) => {
+                match tape[..=data_pointer].iter().rev().step_by(*stride).position(|x| *x == 0) {
+                    Some(offset) => data_pointer -= offset * stride,
+                    None => break checkpoint.0,
+                }
+            }
+            BrainfuckInstruction::ScanRight(stride) => {
+                match tape[data_pointer..].iter().step_by(*stride).position(|x| *x == 0) {
+                    Some(offset) => data_pointer += offset * stride,
+                    None => {
+                        let remaining = tape.len() - data_pointer;
+                        data_pointer += remaining.div_ceil(*stride) * stride;
+                        tape.resize(data_pointer + 1, 0);
+                    }
+                }
+            }
+            BrainfuckInstruction::Print(bytes) => output.extend_from_slice(bytes),
+        }
+
+        index += 1;
+
+        if level == 0 {
+            checkpoint.0 = index;
+        }
+    };
+
+    if stop == 0 {
+        return ir;
+    }
+
+    if level > 0 {
+        let (_, checkpoint_tape, checkpoint_data_pointer, checkpoint_output) = checkpoint;
+        tape = checkpoint_tape;
+        data_pointer = checkpoint_data_pointer;
+        output.truncate(checkpoint_output);
+    }
+
+    let mut result = Vec::new();
+
+    if !output.is_empty() {
+        result.push(BrainfuckInstruction::Print(output));
+    }
+
+    let mut position = 0;
+    for (address, value) in tape.iter().enumerate() {
+        if *value != 0 {
+            move_to(&mut result, position, address);
+            result.push(BrainfuckInstruction::Set(*value));
+            position = address;
+        }
+    }
+    move_to(&mut result, position, data_pointer);
+
+    result.extend_from_slice(&ir[stop..]);
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -182,7 +427,7 @@ mod tests {
 
     #[test]
     fn optimize_works() {
-        let input = parse_str(String::from("[lol]+++[>+++<-][-][>]+[<]"));
+        let input = parse_str(String::from("[lol],+++[>+++<-][-][>]+[<]"));
         let len = input.len();
 
         let result = optimize(input, 10);
@@ -190,6 +435,7 @@ mod tests {
         assert_eq!(
             result,
             vec![
+                BrainfuckInstruction::Read,
                 BrainfuckInstruction::Add(3),
                 BrainfuckInstruction::Open,
                 BrainfuckInstruction::Right(1),
@@ -198,9 +444,9 @@ mod tests {
                 BrainfuckInstruction::Sub(1),
                 BrainfuckInstruction::Close,
                 BrainfuckInstruction::Set(0),
-                BrainfuckInstruction::ScanRight,
+                BrainfuckInstruction::ScanRight(1),
                 BrainfuckInstruction::Add(1),
-                BrainfuckInstruction::ScanLeft
+                BrainfuckInstruction::ScanLeft(1)
             ]
         );
         assert!(result.len() < len);
@@ -257,10 +503,236 @@ mod tests {
         assert_eq!(
             result,
             vec![
-                BrainfuckInstruction::ScanRight,
-                BrainfuckInstruction::ScanLeft
+                BrainfuckInstruction::ScanRight(1),
+                BrainfuckInstruction::ScanLeft(1)
             ]
         );
         assert!(result.len() < len);
     }
+
+    #[test]
+    fn dead_code_removal_handles_empty_input() {
+        assert_eq!(dead_code_removal(Vec::new()), Vec::new());
+    }
+
+    #[test]
+    fn prefix_evaluation_works() {
+        let input = parse_str(String::from("++++++++[>++++++++<-]>+.+.>++<,."));
+
+        let result = prefix_evaluation(input);
+
+        assert_eq!(
+            result,
+            vec![
+                BrainfuckInstruction::Print(vec![65, 66]),
+                BrainfuckInstruction::Right(1),
+                BrainfuckInstruction::Set(66),
+                BrainfuckInstruction::Right(1),
+                BrainfuckInstruction::Set(2),
+                BrainfuckInstruction::Left(1),
+                BrainfuckInstruction::Read,
+                BrainfuckInstruction::Write
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_evaluation_evaluates_whole_programs() {
+        let input = parse_str(String::from("+++[>+++<-]>."));
+
+        let result = prefix_evaluation(input);
+
+        assert_eq!(
+            result,
+            vec![
+                BrainfuckInstruction::Print(vec![9]),
+                BrainfuckInstruction::Right(1),
+                BrainfuckInstruction::Set(9)
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_evaluation_rolls_back_loops_containing_reads() {
+        let input = parse_str(String::from("++.[>,<-]"));
+
+        let result = prefix_evaluation(input);
+
+        assert_eq!(
+            result,
+            vec![
+                BrainfuckInstruction::Print(vec![2]),
+                BrainfuckInstruction::Set(2),
+                BrainfuckInstruction::Open,
+                BrainfuckInstruction::Right(1),
+                BrainfuckInstruction::Read,
+                BrainfuckInstruction::Left(1),
+                BrainfuckInstruction::Sub(1),
+                BrainfuckInstruction::Close
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_evaluation_stops_when_budget_is_exhausted() {
+        let input = parse_str(String::from("+.[]"));
+
+        let result = prefix_evaluation(input);
+
+        assert_eq!(
+            result,
+            vec![
+                BrainfuckInstruction::Print(vec![1]),
+                BrainfuckInstruction::Set(1),
+                BrainfuckInstruction::Open,
+                BrainfuckInstruction::Close
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_evaluation_ignores_programs_starting_with_read() {
+        let input = parse_str(String::from(",[>+<-]"));
+
+        let result = prefix_evaluation(input.clone());
+
+        assert_eq!(result, input);
+    }
+
+    #[test]
+    fn scan_loop_removal_handles_strides() {
+        let input = contraction(parse_str(String::from("[>>][<<<]")));
+
+        let result = scan_loop_removal(input);
+
+        assert_eq!(
+            result,
+            vec![
+                BrainfuckInstruction::ScanRight(2),
+                BrainfuckInstruction::ScanLeft(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_evaluation_handles_strided_scans() {
+        let input = scan_loop_removal(contraction(parse_str(String::from(
+            "+>>+>>+<<<<[>>]<+[<<]>.",
+        ))));
+
+        let result = prefix_evaluation(input);
+
+        assert_eq!(
+            result,
+            vec![
+                BrainfuckInstruction::Print(vec![1]),
+                BrainfuckInstruction::Set(1),
+                BrainfuckInstruction::Right(2),
+                BrainfuckInstruction::Set(1),
+                BrainfuckInstruction::Right(2),
+                BrainfuckInstruction::Set(1),
+                BrainfuckInstruction::Right(1),
+                BrainfuckInstruction::Set(1),
+                BrainfuckInstruction::Left(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn clear_loop_removal_handles_odd_counts() {
+        let input = contraction(parse_str(String::from("[+][---]")));
+
+        let result = clear_loop_removal(input);
+
+        assert_eq!(
+            result,
+            vec![BrainfuckInstruction::Set(0), BrainfuckInstruction::Set(0)]
+        );
+    }
+
+    #[test]
+    fn clear_loop_removal_handles_even_counts_when_safe() {
+        let input = contraction(parse_str(String::from(",[-]++[--]+[--][++]")));
+
+        let result = clear_loop_removal(input);
+
+        assert_eq!(
+            result,
+            vec![
+                BrainfuckInstruction::Read,
+                BrainfuckInstruction::Set(2),
+                BrainfuckInstruction::Set(1),
+                BrainfuckInstruction::Open,
+                BrainfuckInstruction::Sub(2),
+                BrainfuckInstruction::Close,
+                BrainfuckInstruction::Set(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn clear_loop_removal_ignores_even_counts_when_unsafe() {
+        let input = contraction(parse_str(String::from(",[--]")));
+
+        let result = clear_loop_removal(input.clone());
+
+        assert_eq!(result, input);
+    }
+
+    #[test]
+    fn clear_loop_removal_folds_following_adjustments() {
+        let input = contraction(parse_str(String::from("[-]+++--[+]-")));
+
+        let result = clear_loop_removal(input);
+
+        assert_eq!(
+            result,
+            vec![BrainfuckInstruction::Set(1), BrainfuckInstruction::Set(255)]
+        );
+    }
+
+    #[test]
+    fn cancellation_works() {
+        let input = contraction(parse_str(String::from("+++-->><<<,----++>><")));
+
+        let result = cancellation(input);
+
+        assert_eq!(
+            result,
+            vec![
+                BrainfuckInstruction::Add(1),
+                BrainfuckInstruction::Left(1),
+                BrainfuckInstruction::Read,
+                BrainfuckInstruction::Sub(2),
+                BrainfuckInstruction::Right(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn cancellation_removes_no_ops() {
+        let input = contraction(parse_str(String::from("+-><,++--<<>>")));
+
+        let result = cancellation(input);
+
+        assert_eq!(result, vec![BrainfuckInstruction::Read]);
+    }
+
+    #[test]
+    fn cancellation_folds_adjustments_into_set() {
+        let input = vec![
+            BrainfuckInstruction::Add(7),
+            BrainfuckInstruction::Set(4),
+            BrainfuckInstruction::Add(3),
+            BrainfuckInstruction::Sub(10),
+            BrainfuckInstruction::Write,
+        ];
+
+        let result = cancellation(input);
+
+        assert_eq!(
+            result,
+            vec![BrainfuckInstruction::Set(253), BrainfuckInstruction::Write]
+        );
+    }
 }
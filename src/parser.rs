@@ -2,6 +2,17 @@
 
 use crate::ir::BrainfuckInstruction;
 
+/// The location of a Brainfuck instruction in its source code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    /// The index of the character in the source code.
+    pub offset: usize,
+    /// The line the character is on, starting at 1.
+    pub line: usize,
+    /// The column the character is in, starting at 1.
+    pub column: usize,
+}
+
 /// Parses a sequence of BrainfuckInstructions from a string.
 /// Ignores all non-Brainfuck characters.
 ///
@@ -41,6 +52,61 @@ pub fn parse(code: &[char]) -> Vec<BrainfuckInstruction> {
     result
 }
 
+/// Checks that every `[` in a sequence of BrainfuckInstructions has a matching `]` and vice versa.
+///
+/// # Arguments
+///
+/// * `code` - The sequence of BrainfuckInstructions to check.
+pub fn check_brackets(code: &[BrainfuckInstruction]) -> Result<(), String> {
+    let mut depth = 0usize;
+    for instruction in code {
+        match instruction {
+            BrainfuckInstruction::Open => depth += 1,
+            BrainfuckInstruction::Close => {
+                depth = depth.checked_sub(1).ok_or("Got a ] with no matching [")?;
+            }
+            _ => {}
+        }
+    }
+
+    if depth != 0 {
+        Err(String::from("Got a [ with no matching ]"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Finds the Position of every instruction that `parse_str` produces from the same source code.
+/// The result has one entry per instruction, in the same order.
+///
+/// # Arguments
+///
+/// * `code` - The Brainfuck source code to map.
+pub fn positions(code: &str) -> Vec<Position> {
+    let mut result = Vec::new();
+    let mut line = 1;
+    let mut column = 1;
+
+    for (offset, c) in code.chars().enumerate() {
+        if "+-><,.[]".contains(c) {
+            result.push(Position {
+                offset,
+                line,
+                column,
+            });
+        }
+
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -100,4 +166,29 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn positions_locates_brainfuck_instructions() {
+        let code = "+ a\n[-]";
+
+        let result = positions(code);
+
+        assert_eq!(result.len(), parse_str(code.to_string()).len());
+        assert_eq!(
+            result,
+            vec![
+                Position { offset: 0, line: 1, column: 1 },
+                Position { offset: 4, line: 2, column: 1 },
+                Position { offset: 5, line: 2, column: 2 },
+                Position { offset: 6, line: 2, column: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn check_brackets_finds_unmatched_brackets() {
+        assert_eq!(check_brackets(&parse_str(String::from("+[[-]>]"))), Ok(()));
+        assert_eq!(check_brackets(&parse_str(String::from("+[[-]>"))), Err(String::from("Got a [ with no matching ]")));
+        assert_eq!(check_brackets(&parse_str(String::from("-]["))), Err(String::from("Got a ] with no matching [")));
+    }
 }
@@ -0,0 +1,253 @@
+//! Profiling of Brainfuck programs.
+//!
+//! A Profile counts how many times each instruction was executed and how many iterations each
+//! loop ran for, and can produce a report of the loops where most of the time was spent.
+
+use crate::ir::BrainfuckInstruction;
+use crate::parser::Position;
+
+/// The maximum number of characters of code shown for each loop in a report.
+const SNIPPET_LENGTH: usize = 40;
+
+/// Execution counts collected by an Interpreter in profiling mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// The number of times each instruction was executed, indexed by code address.
+    pub counts: Vec<u64>,
+    /// The number of iterations of each loop, indexed by the code address of its `[`.
+    pub iterations: Vec<u64>,
+}
+
+/// Statistics about a single loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    /// The code address of the `[`.
+    pub open: usize,
+    /// The code address of the matching `]`.
+    pub close: usize,
+    /// The number of times the body of the loop was entered.
+    pub iterations: u64,
+    /// The number of steps spent in the loop, including its brackets and any nested loops.
+    pub steps: u64,
+}
+
+impl Profile {
+    /// Creates a new, empty Profile for a program.
+    ///
+    /// # Arguments
+    ///
+    /// * `length` - The number of instructions in the program.
+    pub fn new(length: usize) -> Self {
+        Self {
+            counts: vec![0; length],
+            iterations: vec![0; length],
+        }
+    }
+
+    /// Returns the total number of steps recorded.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns statistics for every loop in a program, hottest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The program the Profile was collected from.
+    pub fn loops(&self, code: &[BrainfuckInstruction]) -> Vec<Loop> {
+        let mut result = Vec::new();
+        let mut stack = Vec::new();
+
+        for (address, instruction) in code.iter().enumerate() {
+            match instruction {
+                BrainfuckInstruction::Open => stack.push(address),
+                BrainfuckInstruction::Close => {
+                    let open = stack.pop().expect("Got a ] with no matching [");
+                    result.push(Loop {
+                        open,
+                        close: address,
+                        iterations: self.iterations[open],
+                        steps: self.counts[open..=address].iter().sum(),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        result.sort_by(|a, b| b.steps.cmp(&a.steps).then(a.open.cmp(&b.open)));
+        result
+    }
+}
+
+/// Produces a report of the hottest loops in a Profile.
+/// Loops are located by their source positions if `positions` is given, otherwise by their code addresses.
+///
+/// # Arguments
+///
+/// * `profile` - The Profile to report on.
+/// * `code` - The program the Profile was collected from.
+/// * `positions` - The source Position of each instruction, as returned by `parser::positions`.
+/// * `limit` - The maximum number of loops to include.
+pub fn report(
+    profile: &Profile,
+    code: &[BrainfuckInstruction],
+    positions: Option<&[Position]>,
+    limit: usize,
+) -> String {
+    let total = profile.total();
+    let mut result = format!("{} steps in total\n", total);
+
+    let loops = profile.loops(code);
+    if loops.is_empty() {
+        return result;
+    }
+
+    result.push_str(&format!(
+        "{:>7}  {:>12}  {:>12}  {:<15}  code\n",
+        "share", "steps", "iterations", "location"
+    ));
+    for l in loops.iter().filter(|l| l.steps > 0).take(limit) {
+        let location = match positions {
+            Some(positions) => format!(
+                "{}:{}-{}:{}",
+                positions[l.open].line,
+                positions[l.open].column,
+                positions[l.close].line,
+                positions[l.close].column
+            ),
+            None => format!("{}-{}", l.open, l.close),
+        };
+        let share = if total > 0 {
+            l.steps as f64 * 100.0 / total as f64
+        } else {
+            0.0
+        };
+
+        result.push_str(&format!(
+            "{:>6.2}%  {:>12}  {:>12}  {:<15}  {}\n",
+            share,
+            l.steps,
+            l.iterations,
+            location,
+            snippet(&code[l.open..=l.close])
+        ));
+    }
+
+    result
+}
+
+/// Renders a sequence of BrainfuckInstructions as Brainfuck, truncated to `SNIPPET_LENGTH` characters.
+fn snippet(code: &[BrainfuckInstruction]) -> String {
+    let mut result = String::new();
+
+    for instruction in code {
+        match instruction {
+            BrainfuckInstruction::Add(count) => result.push_str(&"+".repeat(*count as usize)),
+            BrainfuckInstruction::Sub(count) => result.push_str(&"-".repeat(*count as usize)),
+            BrainfuckInstruction::Right(count) => result.push_str(&">".repeat(*count)),
+            BrainfuckInstruction::Left(count) => result.push_str(&"<".repeat(*count)),
+            BrainfuckInstruction::Read => result.push(','),
+            BrainfuckInstruction::Write => result.push('.'),
+            BrainfuckInstruction::Open => result.push('['),
+            BrainfuckInstruction::Close => result.push(']'),
+            BrainfuckInstruction::Set(value) => {
+                result.push_str("[-]");
+                result.push_str(&"+".repeat(*value as usize));
+            }
+            BrainfuckInstruction::ScanLeft(stride) => result.push_str(&format!("[{}]", "<".repeat(*stride))),
+            BrainfuckInstruction::ScanRight(stride) => result.push_str(&format!("[{}]", ">".repeat(*stride))),
+            BrainfuckInstruction::Print(bytes) => result.push_str(&format!("(print {})", bytes.len())),
+        }
+
+        if result.chars().count() > SNIPPET_LENGTH {
+            break;
+        }
+    }
+
+    if result.chars().count() > SNIPPET_LENGTH {
+        result = result.chars().take(SNIPPET_LENGTH - 3).collect();
+        result.push_str("...");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::Interpreter;
+    use crate::parser;
+
+    fn profile(source: &str) -> (Vec<BrainfuckInstruction>, Profile) {
+        let code = parser::parse_str(String::from(source));
+        let mut interp = Interpreter::new(code.clone());
+        interp.set_profiling(true);
+        interp.run();
+        (code, interp.profile().unwrap().clone())
+    }
+
+    #[test]
+    fn interpreter_counts_instructions_and_iterations() {
+        let (code, profile) = profile("+++[>++[>+<-]<-]");
+
+        assert_eq!(profile.total(), 55);
+        assert_eq!(profile.counts[0], 1);
+        assert_eq!(profile.counts[8], 6);
+        assert_eq!(profile.iterations[3], 3);
+        assert_eq!(profile.iterations[7], 6);
+
+        let loops = profile.loops(&code);
+        assert_eq!(
+            loops,
+            vec![
+                Loop {
+                    open: 3,
+                    close: 15,
+                    iterations: 3,
+                    steps: 52,
+                },
+                Loop {
+                    open: 7,
+                    close: 12,
+                    iterations: 6,
+                    steps: 33,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn report_shows_hottest_loops_with_source_ranges() {
+        let source = "+++\n[>++\n  [>+<-]<-]";
+        let (code, profile) = profile(source);
+        let positions = parser::positions(source);
+
+        let result = report(&profile, &code, Some(&positions), 1);
+
+        assert_eq!(
+            result,
+            [
+                "55 steps in total",
+                "  share         steps    iterations  location         code",
+                " 94.55%            52             3  2:1-3:11         [>++[>+<-]<-]",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn report_uses_code_addresses_without_source_positions() {
+        let (code, profile) = profile("++[-]");
+
+        let result = report(&profile, &code, None, 10);
+
+        assert!(result.ends_with("  2-4              [-]\n"), "{}", result);
+    }
+
+    #[test]
+    fn snippet_truncates_long_loops() {
+        let code = parser::parse_str("[".to_string() + &"+".repeat(100) + "]");
+
+        assert_eq!(snippet(&code), "[".to_string() + &"+".repeat(36) + "...");
+    }
+}
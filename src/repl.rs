@@ -1,138 +1,982 @@
-//! The interactive shell for bfkit.
+//! The interactive shell for bfkit, which can also run scripts of debugger commands.
 
-use std::io::{stdin, stdout, Write};
-use crate::parser;
-use crate::interp::{Interpreter, StopReason};
-use std::process::exit;
+use std::convert::TryFrom;
+use std::io::{BufRead, Read, Write};
+use std::path::PathBuf;
+use crate::expr;
+use crate::listing;
+use crate::parser::{self, Position};
+use crate::interp::{InputQueue, Interpreter, OutputBuffer, StopReason, WatchKind, HISTORY_STEP_SIZE};
+use crate::ir::BrainfuckInstruction;
+use crate::snapshot::Snapshot;
+use std::fs::{self, File};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
 
-/// Runs the brkit interactive Read-Evaluate-Print-Loop, including a gdb-style debugger.
-pub fn repl(source: String) {
-    let code = parser::parse_str(source);
-    let mut interp = Interpreter::new(code.clone());
+/// The number of steps the debugger remembers for reverse execution by default, which takes
+/// `HISTORY_LIMIT * HISTORY_STEP_SIZE` bytes of memory.
+const HISTORY_LIMIT: usize = 1_000_000;
 
-    let stdin = stdin();
+/// The number of source lines shown by the `list` command.
+const LIST_LINES: usize = 10;
 
-    println!("Welcome to bfkit! Type `help` for more information.");
-    loop {
-        print!("> ");
-        stdout().flush().unwrap();
-
-        let mut buffer = String::new();
-        stdin.read_line(&mut buffer).unwrap();
-        let buffer = buffer.trim();
-        let parts: Vec<&str> = buffer.split(" ").collect();
-
-        match parts[0] {
-            "help" | "h" => {
-                println!("Commands:");
-                println!("    help (h)");
-                println!("    quit (q)");
-                println!("    run (r)");
-                println!("    break (b)");
-                println!("    delete (d)");
-                println!("    step (s)");
-                println!("    print (p)");
-                println!("    assign (a)");
-                println!("    jump (j)");
-                println!("    select");
-            }
+/// The number of instructions shown by the `disassemble` command.
+const DISASSEMBLE_INSTRUCTIONS: usize = 10;
+
+/// The number of tape cells shown by the `x` command when no count is given.
+const EXAMINE_CELLS: usize = 32;
+
+const HELP: &str = "Commands:
+    help (h)
+    quit (q)
+    run (r)
+    break (b) <address> [if <condition>]
+    condition <address> [<condition>]
+    ignore <address> <count>
+    delete (d)
+    watch (w) <address> [read|write|change|equals <value>]
+    unwatch <address> [read|write|change|equals <value>]
+    step (s) [<count>]
+    next (n)
+    finish
+    until (u) <address>
+    continue (c) [<count>]
+    reverse-step (rs)
+    reverse-continue (rc)
+    rewind <steps>
+    history [<steps>]
+    print (p)
+    x[/<count>] [<address>]
+    assign (a)
+    jump (j)
+    select
+    list (l) [<line>]
+    disassemble (dis) [<address>]
+    save <file>
+    restore <file>
+    input [\"<text>\"|clear]
+    input-file <file>
+    output
+";
+
+/// The names of the commands, for tab completion.
+const COMMANDS: &[&str] = &[
+    "help",
+    "quit",
+    "run",
+    "break",
+    "condition",
+    "ignore",
+    "delete",
+    "watch",
+    "unwatch",
+    "step",
+    "next",
+    "finish",
+    "until",
+    "continue",
+    "reverse-step",
+    "reverse-continue",
+    "rewind",
+    "history",
+    "print",
+    "x",
+    "assign",
+    "jump",
+    "select",
+    "list",
+    "disassemble",
+    "save",
+    "restore",
+    "input",
+    "input-file",
+    "output",
+];
+
+/// The file in the home directory that commands entered in the REPL are saved to.
+const HISTORY_FILE: &str = ".bfkit_history";
+
+/// A debugger session on a single program.
+pub struct Session {
+    code: Vec<BrainfuckInstruction>,
+    positions: Vec<Position>,
+    source: String,
+    interp: Interpreter,
+    stopped_steps: u64,
+    stopped_tape: Vec<u8>,
+    previous_tape: Vec<u8>,
+    input: InputQueue,
+    output: OutputBuffer,
+    shown_output: usize,
+    interrupt: Arc<AtomicBool>,
+    quit: bool,
+}
+
+impl Session {
+    /// Creates a new Session, stopped before the first instruction of a program.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The Brainfuck source code to debug.
+    /// * `input` - Where the program reads its input from once the input queue is empty.
+    pub fn new(source: String, input: Box<dyn Read>) -> Self {
+        let code = parser::parse_str(source.clone());
+        let positions = parser::positions(&source);
+        let input = InputQueue::new(input);
+        let output = OutputBuffer::new();
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut interp = Interpreter::new(code.clone());
+        interp.set_history_limit(HISTORY_LIMIT);
+        interp.set_input(Box::new(input.clone()));
+        interp.set_output(Box::new(output.clone()));
+        interp.set_interrupt(Some(interrupt.clone()));
+        let stopped_tape = interp.tape().to_vec();
+
+        Self {
+            code,
+            positions,
+            source,
+            stopped_steps: interp.steps(),
+            previous_tape: stopped_tape.clone(),
+            stopped_tape,
+            interp,
+            input,
+            output,
+            shown_output: 0,
+            interrupt,
+            quit: false,
+        }
+    }
+
+    /// Returns the Interpreter running the program.
+    pub fn interp(&self) -> &Interpreter {
+        &self.interp
+    }
+
+    /// Returns the Interpreter running the program, to run it without a command.
+    pub fn interp_mut(&mut self) -> &mut Interpreter {
+        &mut self.interp
+    }
+
+    /// Returns everything the program has written.
+    pub fn output(&self) -> Vec<u8> {
+        self.output.contents()
+    }
+
+    /// Returns everything the program has written since the last call.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        let output = self.output.contents();
+        let new = output[self.shown_output..].to_vec();
+        self.shown_output = output.len();
+        new
+    }
+
+    /// Returns the flag that interrupts the program while a command is running it, for a signal handler to set.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    /// Returns true once the `quit` command has been executed.
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Executes a single debugger command, returning what it prints.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command, such as `break 12` or `x/16 0`.
+    pub fn execute(&mut self, command: &str) -> Result<String, String> {
+        // An interrupt that arrived while waiting for the command must not stop it.
+        self.interrupt.store(false, Ordering::Relaxed);
+        let result = self.dispatch(command);
+
+        // Remember the tape at the last two stops so `x` can highlight what the last command changed.
+        if self.interp.steps() != self.stopped_steps {
+            self.previous_tape = std::mem::replace(&mut self.stopped_tape, self.interp.tape().to_vec());
+            self.stopped_steps = self.interp.steps();
+        }
+
+        result
+    }
+
+    fn dispatch(&mut self, command: &str) -> Result<String, String> {
+        let parts = tokenize(command)?;
+        let interp = &mut self.interp;
+        let ok = || Ok(String::from("OK\n"));
+
+        match parts.first().copied().unwrap_or("") {
+            "help" | "h" => Ok(String::from(HELP)),
             "quit" | "q" => {
-                println!("OK");
-                exit(0);
+                self.quit = true;
+                ok()
             }
-            "run" | "r" => match interp.run() {
-                StopReason::Breakpoint(address) => {
-                    println!("Hit breakpoint at {} ({:?})", address, code[address])
-                }
-                StopReason::Done => println!("OK"),
-            },
-            "break" | "b" => {
-                if parts.len() != 2 {
-                    eprintln!("Invalid syntax!");
+            "run" | "r" => {
+                let reason = interp.run();
+                Ok(report(interp, reason))
+            }
+            "reverse-continue" | "rc" => {
+                let reason = interp.reverse_run();
+                Ok(report(interp, reason))
+            }
+            "reverse-step" | "rs" => {
+                if interp.step_back() {
+                    Ok(report(interp, StopReason::Stepped))
                 } else {
-                    match parts[1].parse::<usize>() {
-                        Ok(address) => match interp.set_breakpoint(address) {
-                            Ok(_) => println!("OK"),
-                            Err(e) => eprintln!("{}", e),
-                        },
-                        Err(_) => eprintln!("Invalid address: {}", parts[1]),
-                    }
+                    Err(String::from("No more history!"))
                 }
             }
-            "delete" | "d" => {
-                if parts.len() != 2 {
-                    eprintln!("Invalid syntax!");
-                } else {
-                    match parts[1].parse::<usize>() {
-                        Ok(address) => {
-                            interp.delete_breakpoint(address);
-                            println!("OK");
-                        }
-                        Err(_) => eprintln!("Invalid address: {}", parts[1]),
+            "rewind" => {
+                syntax(parts.len() == 2)?;
+                interp.rewind(count(parts[1])?)?;
+                ok()
+            }
+            "history" => {
+                syntax(parts.len() <= 2)?;
+                match parts.get(1) {
+                    None => Ok(format!(
+                        "{} step(s) recorded in {} bytes, {} executed\n",
+                        interp.history_len(),
+                        interp.history_len() * HISTORY_STEP_SIZE,
+                        interp.steps()
+                    )),
+                    Some(limit) => {
+                        interp.set_history_limit(count(limit)?);
+                        ok()
                     }
                 }
             }
-            "step" | "s" => {
-                interp.step();
+            "break" | "b" => {
+                syntax(parts.len() == 2 || (parts.len() >= 4 && parts[2] == "if"))?;
+                let address = address(parts[1])?;
+                let condition = if parts.len() > 2 {
+                    Some(expr::parse(rest_of_line(command, parts[3]))?)
+                } else {
+                    None
+                };
+                interp.set_breakpoint(address)?;
+                interp.set_breakpoint_condition(address, condition)?;
+                ok()
             }
-            "print" | "p" => {
-                if parts.len() != 2 {
-                    eprintln!("Invalid syntax!");
+            "condition" => {
+                syntax(parts.len() >= 2)?;
+                let address = address(parts[1])?;
+                let condition = if parts.len() > 2 {
+                    Some(expr::parse(rest_of_line(command, parts[2]))?)
                 } else {
-                    match parts[1].parse::<usize>() {
-                        Ok(address) => match interp.get(address) {
-                            Ok(value) => println!("{}", value),
-                            Err(e) => eprintln!("{}", e),
-                        },
-                        Err(_) => eprintln!("Invalid address: {}", parts[1]),
-                    }
+                    None
+                };
+                interp.set_breakpoint_condition(address, condition)?;
+                ok()
+            }
+            "ignore" => {
+                syntax(parts.len() == 3)?;
+                let address = address(parts[1])?;
+                interp.set_breakpoint_ignore_count(address, count(parts[2])?)?;
+                ok()
+            }
+            "delete" | "d" => {
+                syntax(parts.len() == 2)?;
+                interp.delete_breakpoint(address(parts[1])?);
+                ok()
+            }
+            "watch" | "w" => {
+                syntax(parts.len() >= 2)?;
+                let address = address(parts[1])?;
+                let kind = parse_watch_kind(&parts[2..])?;
+                interp.set_watchpoint(address, kind.unwrap_or(WatchKind::Change))?;
+                ok()
+            }
+            "unwatch" => {
+                syntax(parts.len() >= 2)?;
+                let address = address(parts[1])?;
+                interp.delete_watchpoint(address, parse_watch_kind(&parts[2..])?);
+                ok()
+            }
+            "step" | "s" => {
+                syntax(parts.len() <= 2)?;
+                let count = parts.get(1).map_or(Ok(1), |c| positive_count(c))?;
+                let reason = interp.step_many(count);
+                Ok(report(interp, reason))
+            }
+            "next" | "n" => {
+                let reason = interp.step_over();
+                Ok(report(interp, reason))
+            }
+            "finish" => {
+                let reason = interp.finish()?;
+                Ok(report(interp, reason))
+            }
+            "until" | "u" => {
+                syntax(parts.len() == 2)?;
+                let reason = interp.run_until(address(parts[1])?)?;
+                Ok(report(interp, reason))
+            }
+            "continue" | "c" => {
+                syntax(parts.len() <= 2)?;
+                let count = parts.get(1).map_or(Ok(1), |c| positive_count(c))?;
+                let mut result = String::new();
+                // Like gdb, a count ignores the breakpoint we are stopped at that many times, minus one.
+                let address = interp.instruction_pointer();
+                if count > 1 && interp.set_breakpoint_ignore_count(address, count - 1).is_ok() {
+                    result.push_str(&format!(
+                        "Will ignore the next {} crossing(s) of breakpoint {}\n",
+                        count - 1,
+                        address
+                    ));
                 }
+                let reason = interp.run();
+                result.push_str(&report(interp, reason));
+                Ok(result)
+            }
+            "print" | "p" => {
+                syntax(parts.len() == 2)?;
+                Ok(format!("{}\n", interp.get(address(parts[1])?)?))
             }
             "assign" | "a" => {
-                if parts.len() != 3 {
-                    eprintln!("Invalid syntax!");
-                } else {
-                    match parts[1].parse::<usize>() {
-                        Ok(address) => match parts[2].parse::<u8>() {
-                            Ok(value) => match interp.set(address, value) {
-                                Ok(_) => println!("OK"),
-                                Err(e) => eprintln!("{}", e),
-                            },
-                            Err(_) => eprintln!("Invalid byte: {}", parts[2]),
-                        },
-                        Err(_) => eprintln!("Invalid address: {}", parts[1]),
-                    }
-                }
+                syntax(parts.len() == 3)?;
+                let address = address(parts[1])?;
+                let value = byte(parts[2])?;
+                interp.set(address, value)?;
+                ok()
             }
             "jump" | "j" => {
-                if parts.len() != 2 {
-                    eprintln!("Invalid syntax!");
-                } else {
-                    match parts[1].parse::<usize>() {
-                        Ok(address) => match interp.jump(address) {
-                            Ok(_) => println!("OK"),
-                            Err(e) => eprintln!("{}", e),
-                        },
-                        Err(_) => eprintln!("Invalid address: {}", parts[1]),
-                    }
-                }
+                syntax(parts.len() == 2)?;
+                interp.jump(address(parts[1])?)?;
+                ok()
             }
             "select" => {
-                if parts.len() != 2 {
-                    eprintln!("Invalid syntax!");
+                syntax(parts.len() == 2)?;
+                interp.select(address(parts[1])?)?;
+                ok()
+            }
+            "list" | "l" => {
+                // Source positions only apply while the Interpreter runs the program it was started with.
+                let current = if interp.code() == &self.code[..] {
+                    self.positions.get(interp.instruction_pointer()).cloned()
                 } else {
-                    match parts[1].parse::<usize>() {
-                        Ok(address) => match interp.select(address) {
-                            Ok(_) => println!("OK"),
-                            Err(e) => eprintln!("{}", e),
-                        },
-                        Err(_) => eprintln!("Invalid address: {}", parts[1]),
+                    None
+                };
+                let center = match parts.get(1) {
+                    Some(line) => number(line)
+                        .filter(|line| (1..=self.source.lines().count()).contains(line))
+                        .ok_or_else(|| format!("Invalid line: {}", line))?,
+                    None => current.map_or(1, |position| position.line),
+                };
+                Ok(listing::list(&self.source, current, center, LIST_LINES))
+            }
+            "disassemble" | "dis" => {
+                let center = match parts.get(1) {
+                    Some(address) => match self::address(address)? {
+                        address if address < interp.code().len() => address,
+                        address => return Err(format!("Address out of bounds: {}", address)),
+                    },
+                    None => interp.instruction_pointer(),
+                };
+                Ok(listing::disassemble(interp, center, DISASSEMBLE_INSTRUCTIONS))
+            }
+            "save" => {
+                syntax(parts.len() == 2)?;
+                let path = text(parts[1])?;
+                File::create(&path)
+                    .and_then(|file| interp.snapshot().write(file))
+                    .map_err(|e| format!("Failed to save {}: {}", path, e))?;
+                ok()
+            }
+            "restore" => {
+                syntax(parts.len() == 2)?;
+                let path = text(parts[1])?;
+                let snapshot = File::open(&path)
+                    .and_then(Snapshot::read)
+                    .map_err(|e| format!("Failed to restore {}: {}", path, e))?;
+                interp.restore(snapshot)?;
+                ok()
+            }
+            "input" => match parts[1..] {
+                [] => match self.input.queued() {
+                    queued if queued.is_empty() => Ok(String::from("The input queue is empty\n")),
+                    queued => Ok(format!("\"{}\"\n", queued.escape_ascii())),
+                },
+                ["clear"] => {
+                    self.input.clear();
+                    ok()
+                }
+                [text] => {
+                    self.input.push(&unquote(text)?);
+                    ok()
+                }
+                _ => Err(String::from("Invalid syntax!")),
+            },
+            "input-file" => {
+                syntax(parts.len() == 2)?;
+                let path = text(parts[1])?;
+                let bytes = fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+                self.input.push(&bytes);
+                ok()
+            }
+            "output" => {
+                let mut output = String::from_utf8_lossy(&self.output.contents()).into_owned();
+                if !output.is_empty() && !output.ends_with('\n') {
+                    output.push('\n');
+                }
+                Ok(output)
+            }
+            name if name == "x" || name.starts_with("x/") => {
+                let count = match name.strip_prefix("x/") {
+                    Some(count) => self::count(count)?,
+                    None => EXAMINE_CELLS,
+                };
+                let start = match parts.get(1) {
+                    Some(address) => self::address(address)?,
+                    None => interp.data_pointer(),
+                };
+                if start >= interp.tape().len() {
+                    return Err(format!("Address out of bounds: {}", start));
+                }
+                Ok(listing::examine(interp.tape(), start, count, interp.data_pointer(), &self.previous_tape))
+            }
+            _ => Err(format!("Unrecognized command: {}", command)),
+        }
+    }
+}
+
+/// Runs the brkit interactive Read-Evaluate-Print-Loop, including a gdb-style debugger.
+///
+/// # Arguments
+///
+/// * `source` - The Brainfuck source code to debug.
+/// * `input` - Where the program reads its input from once the input queue is empty.
+pub fn repl(source: String, input: Box<dyn Read>) {
+    let mut session = Session::new(source, input);
+    let mut editor = match Editor::<CommandHelper, DefaultHistory>::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("Failed to start the line editor: {}", e);
+            return;
+        }
+    };
+    editor.set_helper(Some(CommandHelper));
+
+    let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Some(history) = &history {
+        // The file does not exist the first time the REPL is used.
+        let _ = editor.load_history(history);
+    }
+
+    // Ctrl-C stops a running program and returns to the prompt instead of killing the debugger.
+    let interrupt = session.interrupt_flag();
+    if let Err(e) = ctrlc::set_handler(move || interrupt.store(true, Ordering::Relaxed)) {
+        eprintln!("Failed to handle Ctrl-C: {}", e);
+    }
+
+    println!("Welcome to bfkit! Type `help` for more information.");
+    while !session.has_quit() {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            // Like gdb, Ctrl-C at the prompt discards the line instead of quitting.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
+        };
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(command);
+
+        let result = session.execute(command);
+        print!("{}", format_output(&session.take_output()));
+        match result {
+            Ok(result) => print!("{}", result),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(e) = editor.save_history(history) {
+            eprintln!("Failed to save {}: {}", history.display(), e);
+        }
+    }
+}
+
+/// Completes command names in the REPL.
+struct CommandHelper;
+
+impl Completer for CommandHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(&line[..pos]))
+    }
+}
+
+impl Hinter for CommandHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CommandHelper {}
+
+impl Validator for CommandHelper {}
+
+impl Helper for CommandHelper {}
+
+/// Returns where the word being completed starts and the command names it could be completed to.
+/// Only the first word of a line is completed.
+fn complete(line: &str) -> (usize, Vec<String>) {
+    let word = line.trim_start();
+    let start = line.len() - word.len();
+    if word.contains(char::is_whitespace) {
+        return (start, Vec::new());
+    }
+
+    let candidates = COMMANDS
+        .iter()
+        .filter(|command| command.starts_with(word))
+        .map(|command| command.to_string())
+        .collect();
+    (start, candidates)
+}
+
+/// Runs a script of debugger commands non-interactively, writing a transcript of the session.
+/// Each command is echoed after a `> ` prompt and followed by everything the program and the command printed.
+/// Blank lines and lines starting with `#` are skipped. The script stops at `quit` or at the first command that
+/// fails, in which case the error is returned along with its line number.
+///
+/// # Arguments
+///
+/// * `source` - The Brainfuck source code to debug.
+/// * `input` - Where the program reads its input from once the input queue is empty.
+/// * `script` - The debugger commands, one per line.
+/// * `transcript` - Where the transcript is written.
+pub fn script(
+    source: String,
+    input: Box<dyn Read>,
+    script: impl BufRead,
+    mut transcript: impl Write,
+) -> Result<(), String> {
+    let mut session = Session::new(source, input);
+
+    for (index, line) in script.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let command = line.trim();
+        if command.is_empty() || command.starts_with('#') {
+            continue;
+        }
+
+        let result = session.execute(command);
+        let output = format_output(&session.take_output());
+        write!(transcript, "> {}\n{}{}", command, output, result.as_deref().unwrap_or(""))
+            .map_err(|e| e.to_string())?;
+        result.map_err(|e| format!("line {}: {}", index + 1, e))?;
+
+        if session.has_quit() {
+            break;
+        }
+    }
+
+    transcript.flush().map_err(|e| e.to_string())
+}
+
+/// Formats output written by the program so that it stands apart from debugger messages.
+/// Every line is prefixed with `| `, including an unterminated last line.
+fn format_output(output: &[u8]) -> String {
+    String::from_utf8_lossy(output)
+        .split_inclusive('\n')
+        .map(|line| format!("| {}\n", line.strip_suffix('\n').unwrap_or(line)))
+        .collect()
+}
+
+/// Parses a double-quoted string argument, which may contain the escapes produced by `escape_ascii`.
+fn unquote(text: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("Invalid string: {}", text);
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .filter(|_| text.len() >= 2)
+        .ok_or_else(invalid)?;
+
+    let mut result = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next().ok_or_else(invalid)? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                'x' => {
+                    let digits: String = chars.by_ref().take(2).collect();
+                    if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(invalid());
                     }
+                    result.push(u8::from_str_radix(&digits, 16).unwrap());
+                    continue;
                 }
+                c @ ('\\' | '"' | '\'') => c,
+                _ => return Err(invalid()),
+            },
+            '"' => return Err(invalid()),
+            c => c,
+        };
+        let mut buffer = [0; 4];
+        result.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+    }
+    Ok(result)
+}
+
+/// Describes the reason the Interpreter stopped running.
+fn report(interp: &Interpreter, reason: StopReason) -> String {
+    match reason {
+        StopReason::Breakpoint(address) => format!(
+            "Hit breakpoint at {} ({:?}), {} time(s)\n",
+            address,
+            interp.code()[address],
+            interp.breakpoint(address).unwrap().hit_count
+        ),
+        StopReason::Watchpoint(watchpoint) => format!(
+            "Hit watchpoint on cell {} ({:?}) at {}, value is now {}\n",
+            watchpoint.address,
+            watchpoint.kind,
+            interp.instruction_pointer(),
+            interp.get(watchpoint.address).unwrap()
+        ),
+        StopReason::Done => String::from("OK\n"),
+        StopReason::BudgetExhausted => format!("Step budget exhausted at {}\n", interp.instruction_pointer()),
+        StopReason::HistoryExhausted => {
+            format!("Reached the start of the history at {}\n", interp.instruction_pointer())
+        }
+        StopReason::Timeout => format!("Timed out at {}\n", interp.instruction_pointer()),
+        StopReason::Interrupted => {
+            let address = interp.instruction_pointer();
+            format!(
+                "Interrupted at {} ({:?}), data pointer at {}\n",
+                address,
+                interp.code()[address],
+                interp.data_pointer()
+            )
+        }
+        StopReason::Stepped => {
+            let address = interp.instruction_pointer();
+            format!(
+                "Stopped at {} ({:?}), data pointer at {}\n",
+                address,
+                interp.code()[address],
+                interp.data_pointer()
+            )
+        }
+    }
+}
+
+pub(crate) fn syntax(valid: bool) -> Result<(), String> {
+    if valid {
+        Ok(())
+    } else {
+        Err(String::from("Invalid syntax!"))
+    }
+}
+
+/// Parses a decimal number, or a hexadecimal one prefixed with `0x`.
+fn number<T: TryFrom<u64>>(text: &str) -> Option<T> {
+    let value = match text.strip_prefix("0x") {
+        Some(digits) => u64::from_str_radix(digits, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    T::try_from(value).ok()
+}
+
+pub(crate) fn address(text: &str) -> Result<usize, String> {
+    number(text).ok_or_else(|| format!("Invalid address: {}", text))
+}
+
+fn count<T: TryFrom<u64>>(text: &str) -> Result<T, String> {
+    number(text).ok_or_else(|| format!("Invalid count: {}", text))
+}
+
+/// Parses the count of `step` and `continue`, which must not be zero.
+pub(crate) fn positive_count(text: &str) -> Result<u64, String> {
+    match count(text)? {
+        0 => Err(format!("Invalid count: {}", text)),
+        count => Ok(count),
+    }
+}
+
+fn byte(text: &str) -> Result<u8, String> {
+    number(text).ok_or_else(|| format!("Invalid byte: {}", text))
+}
+
+/// Returns a word as text, unquoting it if it is a quoted string.
+fn text(word: &str) -> Result<String, String> {
+    if word.starts_with('"') {
+        String::from_utf8(unquote(word)?).map_err(|_| format!("Invalid string: {}", word))
+    } else {
+        Ok(String::from(word))
+    }
+}
+
+/// Splits a command line into words separated by whitespace. A double-quoted string is part of a single word,
+/// quotes included, and may contain whitespace and escaped quotes.
+pub(crate) fn tokenize(line: &str) -> Result<Vec<&str>, String> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (index, c) in line.char_indices() {
+        if quoted {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                quoted = false;
             }
-            _ => {
-                eprintln!("Unrecognized command: {}", buffer);
+        } else if c.is_whitespace() {
+            if let Some(start) = start.take() {
+                words.push(&line[start..index]);
             }
+        } else {
+            start.get_or_insert(index);
+            quoted = c == '"';
         }
     }
-}
\ No newline at end of file
+
+    if quoted {
+        return Err(String::from("Unterminated string"));
+    }
+    if let Some(start) = start {
+        words.push(&line[start..]);
+    }
+    Ok(words)
+}
+
+/// Returns the rest of a command line, starting at one of the words `tokenize` returned for it.
+fn rest_of_line<'a>(line: &'a str, word: &str) -> &'a str {
+    &line[word.as_ptr() as usize - line.as_ptr() as usize..]
+}
+
+/// Parses the optional kind argument of the `watch` and `unwatch` commands.
+fn parse_watch_kind(parts: &[&str]) -> Result<Option<WatchKind>, String> {
+    match parts {
+        [] => Ok(None),
+        ["read"] => Ok(Some(WatchKind::Read)),
+        ["write"] => Ok(Some(WatchKind::Write)),
+        ["change"] => Ok(Some(WatchKind::Change)),
+        ["equals", value] => Ok(Some(WatchKind::Equals(byte(value)?))),
+        _ => Err(String::from("Invalid syntax!")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn transcript(source: &str, commands: &str) -> (String, Result<(), String>) {
+        let mut buffer = Vec::new();
+        let result = script(String::from(source), Box::new(&b"z"[..]), commands.as_bytes(), &mut buffer);
+        (String::from_utf8(buffer).unwrap(), result)
+    }
+
+    #[test]
+    fn script_writes_a_transcript() {
+        let (output, result) = transcript(
+            "++++++++[>++++++++<-]>+.",
+            "# Stop in the middle of the loop.\nbreak 18 if *1 == 24\n\nrun\nprint 0\nx/8 0\ndelete 18\ncontinue\n",
+        );
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            output,
+            [
+                "> break 18 if *1 == 24",
+                "OK",
+                "> run",
+                "Hit breakpoint at 18 (Left(1)), 1 time(s)",
+                "> print 0",
+                "6",
+                "> x/8 0",
+                "0:    6*> 24*   0    0    0    0    0    0    06 18 00 00 00 00 00 00  |........|",
+                "> delete 18",
+                "OK",
+                "> continue",
+                "| A",
+                "OK",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn script_stops_at_the_first_error() {
+        let (output, result) = transcript("+[-]", "step\nbreak 9\nstep\n");
+
+        assert_eq!(result, Err(String::from("line 2: Address out of bounds: 9")));
+        assert_eq!(output, "> step\nStopped at 1 (Open), data pointer at 0\n> break 9\n");
+    }
+
+    #[test]
+    fn script_stops_at_quit() {
+        let (output, result) = transcript("+", "quit\nrun\n");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "> quit\nOK\n");
+    }
+
+    #[test]
+    fn stepping_out_of_the_last_loop_finishes_the_program() {
+        let (output, result) = transcript("+[-]", "step 2\nfinish\n");
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "> step 2\nStopped at 2 (Sub(1)), data pointer at 0\n> finish\nOK\n");
+
+        let (output, result) = transcript("+[-]", "step\nnext\n");
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "> step\nStopped at 1 (Open), data pointer at 0\n> next\nOK\n");
+    }
+
+    #[test]
+    fn step_and_continue_reject_a_count_of_zero() {
+        let (_, result) = transcript("+", "step 0\n");
+        assert_eq!(result, Err(String::from("line 1: Invalid count: 0")));
+
+        let (_, result) = transcript("+", "continue 0\n");
+        assert_eq!(result, Err(String::from("line 1: Invalid count: 0")));
+    }
+
+    #[test]
+    fn list_and_disassemble_reject_lines_and_addresses_past_the_end() {
+        let (_, result) = transcript("+.\n", "list 18446744073709551615\n");
+        assert_eq!(result, Err(String::from("line 1: Invalid line: 18446744073709551615")));
+
+        let (_, result) = transcript("+.\n", "list 2\n");
+        assert_eq!(result, Err(String::from("line 1: Invalid line: 2")));
+
+        let (_, result) = transcript("+.\n", "disassemble 2\n");
+        assert_eq!(result, Err(String::from("line 1: Address out of bounds: 2")));
+    }
+
+    #[test]
+    fn reverse_step_cannot_undo_manual_changes() {
+        let (output, result) = transcript("+++", "step 2\nreverse-step\nassign 0 5\nreverse-step\n");
+
+        assert_eq!(result, Err(String::from("line 4: No more history!")));
+        assert_eq!(
+            output,
+            [
+                "> step 2",
+                "Stopped at 2 (Add(1)), data pointer at 0",
+                "> reverse-step",
+                "Stopped at 1 (Add(1)), data pointer at 0",
+                "> assign 0 5",
+                "OK",
+                "> reverse-step",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn program_input_is_queued_separately_from_commands() {
+        let (output, result) = transcript(
+            ",.,.,.,.,.",
+            "input\ninput \"a b\\x21\"\ninput\nrun\ninput \"\\n\"\noutput\n",
+        );
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            output,
+            [
+                "> input",
+                "The input queue is empty",
+                "> input \"a b\\x21\"",
+                "OK",
+                "> input",
+                "\"a b!\"",
+                "> run",
+                "| a b!z",
+                "OK",
+                "> input \"\\n\"",
+                "OK",
+                "> output",
+                "a b!z",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn arguments_may_be_hex_quoted_or_spaced_out() {
+        let (output, result) = transcript(
+            ",[.,]",
+            "break  0x3   if  *0 ==  0x62\ninput \"a b\\\"c\"\nrun\ncondition 3\ncontinue\n",
+        );
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            output,
+            [
+                "> break  0x3   if  *0 ==  0x62",
+                "OK",
+                "> input \"a b\\\"c\"",
+                "OK",
+                "> run",
+                "| a b",
+                "Hit breakpoint at 3 (Read), 1 time(s)",
+                "> condition 3",
+                "OK",
+                "> continue",
+                "| \"",
+                "Hit breakpoint at 3 (Read), 2 time(s)",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn tokenize_keeps_quoted_strings_together() {
+        assert_eq!(tokenize("  input \"a \\\" b\"  x "), Ok(vec!["input", "\"a \\\" b\"", "x"]));
+        assert_eq!(tokenize(""), Ok(vec![]));
+        assert_eq!(tokenize("input \"a"), Err(String::from("Unterminated string")));
+    }
+
+    #[test]
+    fn complete_only_completes_command_names() {
+        assert_eq!(complete("  in"), (2, vec![String::from("input"), String::from("input-file")]));
+        assert_eq!(complete("re"), (0, vec![
+            String::from("reverse-step"),
+            String::from("reverse-continue"),
+            String::from("rewind"),
+            String::from("restore"),
+        ]));
+        assert_eq!(complete("break 1"), (0, vec![]));
+    }
+
+    #[test]
+    fn interrupts_before_a_command_are_ignored() {
+        let mut session = Session::new(String::from("+++"), Box::new(io::empty()));
+        session.interrupt_flag().store(true, Ordering::Relaxed);
+
+        assert_eq!(session.execute("run"), Ok(String::from("OK\n")));
+    }
+
+    #[test]
+    fn unquote_rejects_invalid_strings() {
+        assert_eq!(unquote(r#""\t\"\xff""#), Ok(vec![b'\t', b'"', 0xff]));
+        assert_eq!(unquote("\""), Err(String::from("Invalid string: \"")));
+        assert_eq!(unquote(r#""\x4""#), Err(String::from(r#"Invalid string: "\x4""#)));
+        assert_eq!(unquote(r#""a"b""#), Err(String::from(r#"Invalid string: "a"b""#)));
+    }
+}
@@ -0,0 +1,318 @@
+//! Snapshots of the complete state of an Interpreter.
+//!
+//! Snapshots are stored as JSON so that they can be inspected and shared. Execution history,
+//! profiles, traces and the input and output streams are not part of a snapshot.
+
+use crate::expr;
+use crate::interp::{Breakpoint, WatchKind, Watchpoint};
+use crate::ir::{self, BrainfuckInstruction, OPCODES};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+/// The version of the snapshot format written by `Snapshot::write`.
+const VERSION: u64 = 1;
+
+/// The longest tape a snapshot may have, so that a corrupt snapshot cannot exhaust memory.
+const MAX_TAPE_LENGTH: usize = 1 << 24;
+
+/// The state of an Interpreter at some point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// The program being interpreted.
+    pub code: Vec<BrainfuckInstruction>,
+    /// The entire tape.
+    pub tape: Vec<u8>,
+    /// The data pointer.
+    pub data_pointer: usize,
+    /// The instruction pointer.
+    pub instruction_pointer: usize,
+    /// The number of instructions executed so far.
+    pub steps: u64,
+    /// The breakpoints, by code address.
+    pub breakpoints: BTreeMap<usize, Breakpoint>,
+    /// The watchpoints.
+    pub watchpoints: Vec<Watchpoint>,
+    /// Input that was read and then given back by reverse execution, in the order it will be read again.
+    pub pending_input: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Converts the Snapshot to JSON.
+    pub fn to_json(&self) -> Value {
+        let code: Vec<Value> = self
+            .code
+            .iter()
+            .map(|instruction| {
+                let (opcode, operand) = instruction.opcode();
+                match (instruction, operand) {
+                    (BrainfuckInstruction::Print(bytes), _) => json!([OPCODES[opcode as usize], bytes]),
+                    (_, Some(operand)) => json!([OPCODES[opcode as usize], operand]),
+                    (_, None) => json!([OPCODES[opcode as usize]]),
+                }
+            })
+            .collect();
+
+        // Most of the tape is usually zero, so trailing zeroes are left out.
+        let length = self.tape.iter().rposition(|x| *x != 0).map_or(0, |i| i + 1);
+
+        let breakpoints: Vec<Value> = self
+            .breakpoints
+            .iter()
+            .map(|(address, breakpoint)| {
+                json!({
+                    "address": address,
+                    "condition": breakpoint.condition.as_ref().map(|c| c.to_string()),
+                    "ignore_count": breakpoint.ignore_count,
+                    "hit_count": breakpoint.hit_count,
+                })
+            })
+            .collect();
+
+        let watchpoints: Vec<Value> = self
+            .watchpoints
+            .iter()
+            .map(|watchpoint| match watchpoint.kind {
+                WatchKind::Read => json!({"address": watchpoint.address, "kind": "read"}),
+                WatchKind::Write => json!({"address": watchpoint.address, "kind": "write"}),
+                WatchKind::Change => json!({"address": watchpoint.address, "kind": "change"}),
+                WatchKind::Equals(value) => {
+                    json!({"address": watchpoint.address, "kind": "equals", "value": value})
+                }
+            })
+            .collect();
+
+        json!({
+            "version": VERSION,
+            "code": code,
+            "tape_length": self.tape.len(),
+            "tape": &self.tape[..length],
+            "data_pointer": self.data_pointer,
+            "instruction_pointer": self.instruction_pointer,
+            "steps": self.steps,
+            "breakpoints": breakpoints,
+            "watchpoints": watchpoints,
+            "pending_input": self.pending_input,
+        })
+    }
+
+    /// Converts JSON produced by `to_json` back to a Snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The JSON to convert.
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        if number(value, "version")? != VERSION {
+            return Err(format!("Unsupported snapshot version: {}", value["version"]));
+        }
+
+        let mut code = Vec::new();
+        for instruction in array(value, "code")? {
+            let name = instruction[0].as_str().ok_or("Invalid instruction")?;
+            let opcode = OPCODES
+                .iter()
+                .position(|n| *n == name)
+                .ok_or_else(|| format!("Unknown instruction: {}", name))? as u8;
+            let (operand, bytes) = if name == "print" {
+                (0, bytes(&instruction[1])?)
+            } else if ir::has_operand(opcode) {
+                (instruction[1].as_u64().ok_or("Invalid instruction")?, Vec::new())
+            } else {
+                (0, Vec::new())
+            };
+            code.push(BrainfuckInstruction::from_opcode(opcode, operand, &bytes)?);
+        }
+
+        let mut tape = bytes(&value["tape"])?;
+        let tape_length = number(value, "tape_length")? as usize;
+        if tape_length > MAX_TAPE_LENGTH {
+            return Err(format!("Tape is longer than {} cells", MAX_TAPE_LENGTH));
+        }
+        if tape.len() > tape_length {
+            return Err(String::from("Tape is longer than tape_length"));
+        }
+        tape.resize(tape_length, 0);
+
+        let mut breakpoints = BTreeMap::new();
+        for breakpoint in array(value, "breakpoints")? {
+            let condition = match breakpoint["condition"].as_str() {
+                Some(condition) => Some(expr::parse(condition)?),
+                None => None,
+            };
+            breakpoints.insert(
+                number(breakpoint, "address")? as usize,
+                Breakpoint {
+                    condition,
+                    ignore_count: number(breakpoint, "ignore_count")?,
+                    hit_count: number(breakpoint, "hit_count")?,
+                },
+            );
+        }
+
+        let mut watchpoints = Vec::new();
+        for watchpoint in array(value, "watchpoints")? {
+            let kind = match watchpoint["kind"].as_str() {
+                Some("read") => WatchKind::Read,
+                Some("write") => WatchKind::Write,
+                Some("change") => WatchKind::Change,
+                Some("equals") => WatchKind::Equals(
+                    u8::try_from(number(watchpoint, "value")?).map_err(|_| "Invalid field: value")?,
+                ),
+                _ => return Err(String::from("Invalid field: kind")),
+            };
+            watchpoints.push(Watchpoint {
+                address: number(watchpoint, "address")? as usize,
+                kind,
+            });
+        }
+
+        Ok(Self {
+            code,
+            tape,
+            data_pointer: number(value, "data_pointer")? as usize,
+            instruction_pointer: number(value, "instruction_pointer")? as usize,
+            steps: number(value, "steps")?,
+            breakpoints,
+            watchpoints,
+            pending_input: bytes(&value["pending_input"])?,
+        })
+    }
+
+    /// Writes the Snapshot as JSON.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the Snapshot is written.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        serde_json::to_writer(&mut writer, &self.to_json())?;
+        writeln!(writer)
+    }
+
+    /// Reads a Snapshot written by `write`.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Where the Snapshot is read from.
+    pub fn read(reader: impl Read) -> io::Result<Self> {
+        let value: Value = serde_json::from_reader(reader)?;
+        Self::from_json(&value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+fn number(value: &Value, name: &str) -> Result<u64, String> {
+    value[name]
+        .as_u64()
+        .ok_or_else(|| format!("Missing or invalid field: {}", name))
+}
+
+fn array<'a>(value: &'a Value, name: &str) -> Result<&'a Vec<Value>, String> {
+    value[name]
+        .as_array()
+        .ok_or_else(|| format!("Missing or invalid field: {}", name))
+}
+
+fn bytes(value: &Value) -> Result<Vec<u8>, String> {
+    value
+        .as_array()
+        .ok_or("Expected an array of bytes")?
+        .iter()
+        .map(|byte| {
+            byte.as_u64()
+                .and_then(|byte| u8::try_from(byte).ok())
+                .ok_or_else(|| String::from("Expected an array of bytes"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{Interpreter, OutputBuffer, StopReason};
+    use crate::optimizer;
+    use crate::parser;
+
+    #[test]
+    fn snapshot_round_trips_through_json() {
+        let code = optimizer::optimize(parser::parse_str(String::from(",[>+<-]>[<]++[>]<.")), 10);
+        let mut interp = Interpreter::new(code);
+        interp.set_input(Box::new(&b"\x05"[..]));
+        interp.set_breakpoint(3).unwrap();
+        interp.set_breakpoint_condition(3, Some(expr::parse("*1 == 2 || dp > 5").unwrap())).unwrap();
+        interp.set_breakpoint(5).unwrap();
+        interp.set_breakpoint_ignore_count(5, 7).unwrap();
+        interp.set_watchpoint(9, WatchKind::Equals(4)).unwrap();
+        interp.set_watchpoint(2, WatchKind::Read).unwrap();
+        interp.run();
+        let snapshot = interp.snapshot();
+
+        let mut buffer = Vec::new();
+        snapshot.write(&mut buffer).unwrap();
+
+        assert_eq!(Snapshot::read(&buffer[..]).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn restore_resumes_execution() {
+        let code = parser::parse_str(String::from("+++++[>++<-]>."));
+        let output = OutputBuffer::new();
+        let mut interp = Interpreter::new(code);
+        interp.set_breakpoint(7).unwrap();
+        interp.set_breakpoint_ignore_count(7, 2).unwrap();
+        interp.set_output(Box::new(OutputBuffer::new()));
+        interp.run();
+        let mut buffer = Vec::new();
+        interp.snapshot().write(&mut buffer).unwrap();
+
+        let mut restored = Interpreter::new(Vec::new());
+        restored.set_output(Box::new(output.clone()));
+        restored.restore(Snapshot::read(&buffer[..]).unwrap()).unwrap();
+
+        assert_eq!(restored.instruction_pointer(), 7);
+        assert_eq!(restored.steps(), interp.steps());
+        assert_eq!(restored.tape()[1], 4);
+        assert_eq!(restored.breakpoint(7).unwrap().hit_count, 1);
+        assert_eq!(restored.run(), StopReason::Breakpoint(7));
+        restored.delete_breakpoint(7);
+        assert_eq!(restored.run(), StopReason::Done);
+        assert_eq!(output.contents(), vec![10]);
+    }
+
+    #[test]
+    fn restore_rejects_invalid_snapshots() {
+        let mut interp = Interpreter::new(parser::parse_str(String::from("+[-]")));
+        let snapshot = interp.snapshot();
+
+        let unbalanced = Snapshot {
+            code: parser::parse_str(String::from("+[-")),
+            ..snapshot.clone()
+        };
+        assert_eq!(interp.restore(unbalanced), Err(String::from("Got a [ with no matching ]")));
+
+        let out_of_bounds = Snapshot {
+            data_pointer: 5000,
+            ..snapshot
+        };
+        assert_eq!(interp.restore(out_of_bounds), Err(String::from("Address out of bounds: 5000")));
+    }
+
+    #[test]
+    fn from_json_reports_errors() {
+        assert_eq!(
+            Snapshot::from_json(&json!({"version": 2})),
+            Err(String::from("Unsupported snapshot version: 2"))
+        );
+        assert_eq!(
+            Snapshot::from_json(&json!({"version": 1, "code": [["jump", 1]]})),
+            Err(String::from("Unknown instruction: jump"))
+        );
+        assert_eq!(
+            Snapshot::from_json(&json!({"version": 1, "code": [["scan_right", 0]]})),
+            Err(String::from("Scan stride must not be zero"))
+        );
+        assert_eq!(
+            Snapshot::from_json(&json!({"version": 1, "code": [], "tape": [], "tape_length": u64::MAX})),
+            Err(String::from("Tape is longer than 16777216 cells"))
+        );
+    }
+}
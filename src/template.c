@@ -63,16 +63,33 @@ static void assert(u8 b, char *msg) {
 
 static const u64 tape_size = __TAPE_SIZE__;
 
+static u8* __scan_left_strided(u8 *tape, u8 *dp, u64 stride) {
+    while(*dp) {
+        assert((u64)(dp - tape) >= stride, "Scan ran off the start of the tape");
+        dp -= stride;
+    }
+    return dp;
+}
+
+static u8* __scan_right_strided(u8 *tape, u8 *dp, u64 stride) {
+    while(*dp) {
+        assert((u64)(dp - tape) + stride < tape_size, "Scan ran off the end of the tape");
+        dp += stride;
+    }
+    return dp;
+}
+
 #define ADJUST(base_offset, delta) *(dp + base_offset) += delta;
 #define SELECT(delta) dp += delta;
-#define READ(base_offset) *(dp + base_offset) = getchar();
+#define READ(base_offset) { s32 c = getchar(); if(c != EOF) *(dp + base_offset) = c; }
 #define WRITE(base_offset) putchar(*(dp + base_offset)); fflush(stdout);
 #define OPEN() while(*dp) {
 #define CLOSE() }
 #define SET(base_offset, value) *(dp + base_offset) = value;
 #define MADD(offset, factor) *(dp + offset) += *dp * factor;
-#define SCAN_LEFT() __scan_left(tape, &dp);
-#define SCAN_RIGHT() dp += (u64)(memchr(dp, 0, tape_size - (dp - tape)) - (void*) dp);
+#define SCAN_LEFT(stride) if(stride == 1) { __scan_left(tape, &dp); } else { dp = __scan_left_strided(tape, dp, stride); }
+#define SCAN_RIGHT(stride) if(stride == 1) { dp += (u64)(memchr(dp, 0, tape_size - (dp - tape)) - (void*) dp); } else { dp = __scan_right_strided(tape, dp, stride); }
+#define PRINT(bytes, count) fwrite(bytes, 1, count, stdout); fflush(stdout);
 
 int main() {
     u8 *tape = (u8*) __alloc(tape_size);
@@ -0,0 +1,657 @@
+//! Recording, reading and summarizing execution traces.
+//!
+//! A trace is a sequence of Records, one for each traced step of an Interpreter. Traces are
+//! written either in a compact binary form or as JSON lines, and `TraceReader` accepts both.
+//!
+//! The binary form starts with the magic bytes `BFTRACE1`. Each record is then encoded as:
+//!
+//! * the number of steps since the previous record, as a LEB128 varint
+//! * the instruction pointer, as a varint
+//! * the opcode, as a byte, followed by the operand as a varint for instructions that have one
+//! * the data pointer, as a varint
+//! * the cell value before and after the step, as two bytes
+//! * a flags byte: bit 0 is set if a byte was read, bit 1 if any bytes were written
+//! * the byte read, if any
+//! * the number of bytes written as a varint followed by the bytes, if any
+//!
+//! Each JSON line holds an object like
+//! `{"step":5,"ip":3,"op":"add","arg":2,"dp":0,"before":1,"after":3}`, with `arg` omitted for
+//! instructions without an operand and `input` and `output` fields present only when some I/O
+//! happened.
+
+use crate::ir::{self, BrainfuckInstruction, OPCODES};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// The bytes at the start of every binary trace.
+const MAGIC: &[u8] = b"BFTRACE1";
+
+/// Format represents the encoding of a trace.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Binary is the compact binary encoding.
+    Binary,
+    /// Json is one JSON object per line.
+    Json,
+}
+
+/// A single traced step.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// The number of instructions executed, including this one.
+    pub step: u64,
+    /// The code address of the instruction.
+    pub instruction_pointer: usize,
+    /// The instruction that was executed.
+    pub instruction: BrainfuckInstruction,
+    /// The data pointer before the instruction was executed.
+    pub data_pointer: usize,
+    /// The value of the cell under the data pointer before the instruction was executed.
+    pub before: u8,
+    /// The value of that same cell after the instruction was executed.
+    pub after: u8,
+    /// The byte read by the instruction, if any.
+    pub input: Option<u8>,
+    /// The bytes written by the instruction.
+    pub output: Vec<u8>,
+}
+
+/// Writes Records to a trace.
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: Format,
+    interval: u64,
+    last_step: u64,
+}
+
+impl Tracer {
+    /// Creates a new Tracer, writing the header of the trace if the format has one.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the trace is written.
+    /// * `format` - The encoding of the trace.
+    /// * `interval` - Only every `interval`th step is recorded.
+    pub fn new(mut writer: Box<dyn Write>, format: Format, interval: u64) -> io::Result<Self> {
+        if format == Format::Binary {
+            writer.write_all(MAGIC)?;
+        }
+
+        Ok(Self {
+            writer,
+            format,
+            interval: interval.max(1),
+            last_step: 0,
+        })
+    }
+
+    /// Returns true if the step with some number should be recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `step` - The number of instructions executed, including the step in question.
+    pub fn wants(&self, step: u64) -> bool {
+        step % self.interval == 0
+    }
+
+    /// Writes a Record to the trace.
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - The Record to write.
+    pub fn record(&mut self, record: &Record) -> io::Result<()> {
+        match self.format {
+            Format::Binary => {
+                let mut buffer = Vec::new();
+                write_varint(&mut buffer, record.step - self.last_step);
+                write_varint(&mut buffer, record.instruction_pointer as u64);
+                // The bytes of a Print are the same as its output, so they are not stored twice.
+                let (opcode, operand) = record.instruction.opcode();
+                buffer.push(opcode);
+                if let Some(operand) = operand {
+                    write_varint(&mut buffer, operand);
+                }
+                write_varint(&mut buffer, record.data_pointer as u64);
+                buffer.push(record.before);
+                buffer.push(record.after);
+                buffer.push(record.input.is_some() as u8 | ((!record.output.is_empty() as u8) << 1));
+                if let Some(input) = record.input {
+                    buffer.push(input);
+                }
+                if !record.output.is_empty() {
+                    write_varint(&mut buffer, record.output.len() as u64);
+                    buffer.extend_from_slice(&record.output);
+                }
+                self.writer.write_all(&buffer)?;
+            }
+            Format::Json => {
+                writeln!(self.writer, "{}", to_json(record))?;
+            }
+        }
+
+        self.last_step = record.step;
+        Ok(())
+    }
+
+    /// Flushes everything written so far to the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Converts a Record to the JSON object used in JSON traces.
+///
+/// # Arguments
+///
+/// * `record` - The Record to convert.
+pub fn to_json(record: &Record) -> Value {
+    let (opcode, operand) = record.instruction.opcode();
+
+    let mut result = Map::new();
+    result.insert(String::from("step"), json!(record.step));
+    result.insert(String::from("ip"), json!(record.instruction_pointer));
+    result.insert(String::from("op"), json!(OPCODES[opcode as usize]));
+    if let Some(operand) = operand {
+        result.insert(String::from("arg"), json!(operand));
+    }
+    result.insert(String::from("dp"), json!(record.data_pointer));
+    result.insert(String::from("before"), json!(record.before));
+    result.insert(String::from("after"), json!(record.after));
+    if let Some(input) = record.input {
+        result.insert(String::from("input"), json!(input));
+    }
+    if !record.output.is_empty() {
+        result.insert(String::from("output"), json!(record.output));
+    }
+    Value::Object(result)
+}
+
+/// Reads the Records of a trace in either format, detecting the format from its first bytes.
+pub struct TraceReader<R: Read> {
+    reader: BufReader<R>,
+    format: Format,
+    last_step: u64,
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Creates a new TraceReader, consuming the header of the trace if it has one.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The trace to read.
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+
+        let format = if reader.fill_buf()?.starts_with(MAGIC) {
+            reader.consume(MAGIC.len());
+            Format::Binary
+        } else {
+            Format::Json
+        };
+
+        Ok(Self {
+            reader,
+            format,
+            last_step: 0,
+        })
+    }
+
+    /// Returns the format of the trace being read.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    fn read_binary(&mut self) -> io::Result<Option<Record>> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let step = self.last_step + read_varint(&mut self.reader)?;
+        let instruction_pointer = read_varint(&mut self.reader)? as usize;
+        let opcode = read_byte(&mut self.reader)?;
+        if opcode as usize >= OPCODES.len() {
+            return Err(invalid(format!("Unknown opcode: {}", opcode)));
+        }
+        let operand = if ir::has_operand(opcode) {
+            read_varint(&mut self.reader)?
+        } else {
+            0
+        };
+        let data_pointer = read_varint(&mut self.reader)? as usize;
+        let before = read_byte(&mut self.reader)?;
+        let after = read_byte(&mut self.reader)?;
+        let flags = read_byte(&mut self.reader)?;
+        let input = if flags & 1 != 0 {
+            Some(read_byte(&mut self.reader)?)
+        } else {
+            None
+        };
+        let mut output = Vec::new();
+        if flags & 2 != 0 {
+            output.resize(read_varint(&mut self.reader)? as usize, 0);
+            self.reader.read_exact(&mut output)?;
+        }
+
+        Ok(Some(Record {
+            step,
+            instruction_pointer,
+            instruction: BrainfuckInstruction::from_opcode(opcode, operand, &output).map_err(invalid)?,
+            data_pointer,
+            before,
+            after,
+            input,
+            output,
+        }))
+    }
+
+    fn read_json(&mut self) -> io::Result<Option<Record>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let value: Value = serde_json::from_str(&line).map_err(invalid)?;
+        let number = |name: &str| {
+            value[name]
+                .as_u64()
+                .ok_or_else(|| invalid(format!("Missing or invalid field: {}", name)))
+        };
+        let byte = |name: &str| {
+            number(name).and_then(|value| u8::try_from(value).map_err(invalid))
+        };
+
+        let op = value["op"]
+            .as_str()
+            .ok_or_else(|| invalid("Missing or invalid field: op"))?;
+        let opcode = OPCODES
+            .iter()
+            .position(|name| *name == op)
+            .ok_or_else(|| invalid(format!("Unknown op: {}", op)))? as u8;
+        let operand = if ir::has_operand(opcode) { number("arg")? } else { 0 };
+        let input = if value.get("input").is_some() {
+            Some(byte("input")?)
+        } else {
+            None
+        };
+        let output = match value.get("output") {
+            Some(output) => output
+                .as_array()
+                .ok_or_else(|| invalid("Missing or invalid field: output"))?
+                .iter()
+                .map(|byte| {
+                    byte.as_u64()
+                        .and_then(|byte| u8::try_from(byte).ok())
+                        .ok_or_else(|| invalid("Missing or invalid field: output"))
+                })
+                .collect::<io::Result<Vec<u8>>>()?,
+            None => Vec::new(),
+        };
+
+        Ok(Some(Record {
+            step: number("step")?,
+            instruction_pointer: number("ip")? as usize,
+            instruction: BrainfuckInstruction::from_opcode(opcode, operand, &output).map_err(invalid)?,
+            data_pointer: number("dp")? as usize,
+            before: byte("before")?,
+            after: byte("after")?,
+            input,
+            output,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match self.format {
+            Format::Binary => self.read_binary(),
+            Format::Json => self.read_json(),
+        };
+
+        match result {
+            Ok(Some(record)) => {
+                self.last_step = record.step;
+                Some(Ok(record))
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Filter selects Records from a trace. Every condition that is set must hold for a Record to match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    /// Only match Records from this step onwards.
+    pub from: Option<u64>,
+    /// Only match Records up to and including this step.
+    pub to: Option<u64>,
+    /// Only match Records of the instruction at this code address.
+    pub instruction_pointer: Option<usize>,
+    /// Only match Records where the data pointer was at this tape address.
+    pub data_pointer: Option<usize>,
+    /// Only match Records of instructions with this opcode, such as `add` or `scan_left`.
+    pub op: Option<String>,
+    /// Only match Records that read or wrote some bytes.
+    pub io: bool,
+}
+
+impl Filter {
+    /// Returns true if a Record matches the Filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - The Record to check.
+    pub fn matches(&self, record: &Record) -> bool {
+        self.from.map_or(true, |from| record.step >= from)
+            && self.to.map_or(true, |to| record.step <= to)
+            && self.instruction_pointer.map_or(true, |ip| record.instruction_pointer == ip)
+            && self.data_pointer.map_or(true, |dp| record.data_pointer == dp)
+            && self.op.as_ref().map_or(true, |op| {
+                OPCODES[record.instruction.opcode().0 as usize] == op
+            })
+            && (!self.io || record.input.is_some() || !record.output.is_empty())
+    }
+}
+
+/// Summary holds statistics about a trace.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    /// The number of Records in the trace.
+    pub records: u64,
+    /// The steps of the first and last Records.
+    pub steps: Option<(u64, u64)>,
+    /// The lowest and highest data pointers.
+    pub data_pointers: Option<(usize, usize)>,
+    /// The number of Records of each opcode.
+    pub ops: HashMap<&'static str, u64>,
+    /// The number of Records of each code address.
+    pub instruction_pointers: HashMap<usize, u64>,
+    /// The number of bytes read.
+    pub input: u64,
+    /// The number of bytes written.
+    pub output: u64,
+}
+
+impl Summary {
+    /// Adds a Record to the Summary.
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - The Record to add.
+    pub fn add(&mut self, record: &Record) {
+        self.records += 1;
+        self.steps = Some(match self.steps {
+            Some((first, _)) => (first, record.step),
+            None => (record.step, record.step),
+        });
+        self.data_pointers = Some(match self.data_pointers {
+            Some((low, high)) => (low.min(record.data_pointer), high.max(record.data_pointer)),
+            None => (record.data_pointer, record.data_pointer),
+        });
+        *self
+            .ops
+            .entry(OPCODES[record.instruction.opcode().0 as usize])
+            .or_insert(0) += 1;
+        *self
+            .instruction_pointers
+            .entry(record.instruction_pointer)
+            .or_insert(0) += 1;
+        self.input += record.input.is_some() as u64;
+        self.output += record.output.len() as u64;
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "records: {}", self.records)?;
+        if let Some((first, last)) = self.steps {
+            writeln!(f, "steps: {} to {}", first, last)?;
+        }
+        if let Some((low, high)) = self.data_pointers {
+            writeln!(f, "data pointer: {} to {}", low, high)?;
+        }
+        writeln!(f, "input: {} bytes", self.input)?;
+        writeln!(f, "output: {} bytes", self.output)?;
+
+        writeln!(f, "instructions:")?;
+        for name in OPCODES.iter() {
+            if let Some(count) = self.ops.get(name) {
+                writeln!(f, "    {:<10} {}", name, count)?;
+            }
+        }
+
+        let mut hottest: Vec<(&usize, &u64)> = self.instruction_pointers.iter().collect();
+        hottest.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(f, "hottest addresses:")?;
+        for (address, count) in hottest.iter().take(10) {
+            writeln!(f, "    {:<10} {}", address, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut result = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = read_byte(reader)?;
+        if shift >= 64 {
+            return Err(invalid("Varint is too long"));
+        }
+        result |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+fn read_byte(reader: &mut impl Read) -> io::Result<u8> {
+    let mut buffer = [0u8; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{Interpreter, OutputBuffer};
+    use crate::optimizer;
+    use crate::parser;
+
+    fn trace(source: &str, input: &[u8], format: Format, interval: u64) -> Vec<u8> {
+        let code = optimizer::optimize(parser::parse_str(String::from(source)), 10);
+        let trace = OutputBuffer::new();
+
+        let mut interp = Interpreter::new(code);
+        interp.set_input(Box::new(io::Cursor::new(input.to_vec())));
+        interp.set_output(Box::new(OutputBuffer::new()));
+        interp.set_tracer(Some(Tracer::new(Box::new(trace.clone()), format, interval).unwrap()));
+        interp.run();
+
+        trace.contents()
+    }
+
+    fn read(trace: &[u8]) -> Vec<Record> {
+        TraceReader::new(trace).unwrap().map(|record| record.unwrap()).collect()
+    }
+
+    #[test]
+    fn interpreter_records_every_step() {
+        let records = read(&trace(",[->+<]>.", b"\x02", Format::Binary, 1));
+
+        assert_eq!(records.len(), 14);
+        assert_eq!(
+            records[0],
+            Record {
+                step: 1,
+                instruction_pointer: 0,
+                instruction: BrainfuckInstruction::Read,
+                data_pointer: 0,
+                before: 0,
+                after: 2,
+                input: Some(2),
+                output: vec![],
+            }
+        );
+        assert_eq!(
+            records[2],
+            Record {
+                step: 3,
+                instruction_pointer: 2,
+                instruction: BrainfuckInstruction::Sub(1),
+                data_pointer: 0,
+                before: 2,
+                after: 1,
+                input: None,
+                output: vec![],
+            }
+        );
+        assert_eq!(records[13].instruction, BrainfuckInstruction::Write);
+        assert_eq!(records[13].output, vec![2]);
+    }
+
+    #[test]
+    fn json_and_binary_traces_agree() {
+        let source = "++++++++[>++++++++<-]>+.,[.,]";
+
+        let binary = trace(source, b"hi\0", Format::Binary, 1);
+        let json = trace(source, b"hi\0", Format::Json, 1);
+
+        assert!(binary.len() < json.len());
+        assert_eq!(read(&binary), read(&json));
+    }
+
+    #[test]
+    fn print_round_trips() {
+        let records = read(&trace("++++++++[>++++++++<-]>+.+.", b"", Format::Binary, 1));
+
+        assert_eq!(
+            records[0].instruction,
+            BrainfuckInstruction::Print(b"AB".to_vec())
+        );
+        assert_eq!(records[0].output, b"AB");
+    }
+
+    #[test]
+    fn replayed_output_is_not_recorded() {
+        let trace = OutputBuffer::new();
+        let mut interp = Interpreter::new(parser::parse_str(String::from("+.")));
+        interp.set_output(Box::new(OutputBuffer::new()));
+        interp.set_history_limit(10);
+        interp.run();
+        interp.step_back();
+        interp.set_tracer(Some(Tracer::new(Box::new(trace.clone()), Format::Binary, 1).unwrap()));
+        interp.run();
+
+        let records = read(&trace.contents());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].output, b"");
+    }
+
+    #[test]
+    fn tracer_respects_interval() {
+        let records = read(&trace(",>+>+>+>+", b"\x01", Format::Json, 3));
+
+        assert_eq!(
+            records.iter().map(|r| r.step).collect::<Vec<_>>(),
+            vec![3, 6, 9]
+        );
+    }
+
+    #[test]
+    fn to_json_omits_missing_fields() {
+        let record = Record {
+            step: 5,
+            instruction_pointer: 3,
+            instruction: BrainfuckInstruction::Open,
+            data_pointer: 1,
+            before: 2,
+            after: 2,
+            input: None,
+            output: vec![],
+        };
+
+        assert_eq!(
+            to_json(&record).to_string(),
+            r#"{"after":2,"before":2,"dp":1,"ip":3,"op":"open","step":5}"#
+        );
+    }
+
+    #[test]
+    fn trace_reader_rejects_invalid_traces() {
+        let mut reader = TraceReader::new(&b"BFTRACE1\x01\x00\x63"[..]).unwrap();
+        assert_eq!(reader.format(), Format::Binary);
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().to_string(),
+            "Unknown opcode: 99"
+        );
+
+        let mut reader = TraceReader::new(&b"{\"step\":1}\n"[..]).unwrap();
+        assert_eq!(reader.format(), Format::Json);
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().to_string(),
+            "Missing or invalid field: op"
+        );
+    }
+
+    #[test]
+    fn filter_and_summary_work() {
+        let records = read(&trace(",[->+<]>.", b"\x02", Format::Binary, 1));
+
+        let filter = Filter {
+            op: Some(String::from("add")),
+            from: Some(4),
+            ..Filter::default()
+        };
+        let matching: Vec<u64> = records
+            .iter()
+            .filter(|r| filter.matches(r))
+            .map(|r| r.step)
+            .collect();
+        assert_eq!(matching, vec![5, 10]);
+
+        let io = Filter {
+            io: true,
+            ..Filter::default()
+        };
+        assert_eq!(records.iter().filter(|r| io.matches(r)).count(), 2);
+
+        let mut summary = Summary::default();
+        for record in &records {
+            summary.add(record);
+        }
+        assert_eq!(summary.records, 14);
+        assert_eq!(summary.steps, Some((1, 14)));
+        assert_eq!(summary.data_pointers, Some((0, 1)));
+        assert_eq!(summary.ops["open"], 1);
+        assert_eq!(summary.ops["close"], 2);
+        assert_eq!(summary.instruction_pointers[&2], 2);
+        assert_eq!(summary.input, 1);
+        assert_eq!(summary.output, 1);
+    }
+}
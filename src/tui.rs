@@ -0,0 +1,650 @@
+//! A full-screen terminal debugger for Brainfuck programs.
+//!
+//! The screen is split into panes for the source code, the tape around the data pointer, the breakpoints and the
+//! output of the program, with a status bar and a command line at the bottom. The unoptimized program is run so
+//! that every instruction maps back to a single character of the source.
+
+use crate::interp::{Interpreter, StopReason};
+use crate::parser::{self, Position};
+use crate::repl::{self, Session};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{self, Cursor, Write};
+use std::time::Duration;
+
+/// The number of instructions executed between checks for key presses while the program is running.
+const RUN_CHUNK: u64 = 100_000;
+
+/// The maximum number of rows given to the output pane, including its title.
+const OUTPUT_HEIGHT: usize = 8;
+
+/// The keyboard shortcuts shown in the status bar.
+const KEYS: &str = "s step  n next  f finish  c continue  b break  : command  q quit";
+
+/// A run of text on the screen.
+#[derive(Debug, Clone, PartialEq)]
+struct Span {
+    text: String,
+    highlight: bool,
+}
+
+impl Span {
+    fn plain(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            highlight: false,
+        }
+    }
+
+    fn highlighted(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            highlight: true,
+        }
+    }
+}
+
+/// A single row of the screen.
+type Line = Vec<Span>;
+
+/// How far a running program is run before it stops by itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Run {
+    /// Runs until a breakpoint or watchpoint is hit or the program finishes.
+    Continue,
+    /// Also stops when the instruction pointer arrives at a code address.
+    Until(usize),
+    /// Also stops after some number of instructions.
+    Steps(u64),
+}
+
+/// The state of the debugger.
+struct App {
+    /// The REPL session that runs the program and executes the commands typed on the command line.
+    session: Session,
+    source: String,
+    positions: Vec<Position>,
+    /// The command being typed, if the command line is active.
+    command: Option<String>,
+    message: String,
+    /// How the program is being run, if it is running.
+    running: Option<Run>,
+    quit: bool,
+}
+
+impl App {
+    fn new(source: String, input: Vec<u8>) -> Self {
+        Self {
+            session: Session::new(source.clone(), Box::new(Cursor::new(input))),
+            positions: parser::positions(&source),
+            source,
+            command: None,
+            message: String::from("Welcome to bfkit! Press : to enter a command."),
+            running: None,
+            quit: false,
+        }
+    }
+
+    fn interp(&self) -> &Interpreter {
+        self.session.interp()
+    }
+
+    fn interp_mut(&mut self) -> &mut Interpreter {
+        self.session.interp_mut()
+    }
+
+    /// Describes a code address by its location in the source.
+    fn location(&self, address: usize) -> String {
+        match self.positions.get(address) {
+            Some(position) => format!("{} ({}:{})", address, position.line, position.column),
+            None => format!("{} (end)", address),
+        }
+    }
+
+    fn report(&mut self, reason: StopReason) {
+        self.message = match reason {
+            StopReason::Breakpoint(address) => format!(
+                "Hit breakpoint at {}, {} time(s)",
+                self.location(address),
+                self.interp().breakpoint(address).unwrap().hit_count
+            ),
+            StopReason::Watchpoint(watchpoint) => format!(
+                "Hit watchpoint on cell {} at {}",
+                watchpoint.address,
+                self.location(self.interp().instruction_pointer())
+            ),
+            StopReason::Done => String::from("Program finished"),
+            StopReason::Stepped => format!("Stopped at {}", self.location(self.interp().instruction_pointer())),
+            StopReason::BudgetExhausted => String::from("Step budget exhausted"),
+            StopReason::HistoryExhausted => String::from("Reached the start of the history"),
+            StopReason::Timeout => String::from("Timed out"),
+            StopReason::Interrupted => String::from("Interrupted"),
+        };
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        // Any key pauses a running program.
+        if self.running.is_some() {
+            self.running = None;
+            self.message = format!("Paused at {}", self.location(self.interp().instruction_pointer()));
+            return;
+        }
+
+        if let Some(command) = &mut self.command {
+            match key.code {
+                KeyCode::Enter => {
+                    let command = self.command.take().unwrap();
+                    self.execute(&command);
+                }
+                KeyCode::Esc => self.command = None,
+                KeyCode::Backspace => {
+                    command.pop();
+                }
+                KeyCode::Char(c) => command.push(c),
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char('s') => self.step(),
+            KeyCode::Char('n') => self.next(),
+            KeyCode::Char('f') => self.finish(),
+            KeyCode::Char('c') => self.start(Run::Continue),
+            KeyCode::Char('b') => self.toggle_breakpoint(self.interp().instruction_pointer()),
+            KeyCode::Char(':') => self.command = Some(String::new()),
+            _ => {}
+        }
+    }
+
+    fn step(&mut self) {
+        let reason = self.interp_mut().step_many(1);
+        self.report(reason);
+    }
+
+    fn next(&mut self) {
+        match self.interp().step_over_target() {
+            Some(target) => self.start(Run::Until(target)),
+            None => self.step(),
+        }
+    }
+
+    fn finish(&mut self) {
+        match self.interp().finish_target() {
+            Ok(target) => self.start(Run::Until(target)),
+            Err(e) => self.message = e,
+        }
+    }
+
+    /// Starts running the program, a chunk at a time in `tick`.
+    fn start(&mut self, run: Run) {
+        if self.interp().is_done() {
+            self.message = String::from("Program finished");
+        } else {
+            self.running = Some(run);
+            self.message = String::from("Running, press any key to pause");
+        }
+    }
+
+    fn toggle_breakpoint(&mut self, address: usize) {
+        if self.interp_mut().delete_breakpoint(address) {
+            self.message = format!("Deleted breakpoint at {}", self.location(address));
+        } else {
+            self.message = match self.interp_mut().set_breakpoint(address) {
+                Ok(_) => format!("Set breakpoint at {}", self.location(address)),
+                Err(e) => e,
+            };
+        }
+    }
+
+    /// Executes a command typed on the command line. Commands that run the program run it a chunk at a time, like
+    /// the keys do, and the rest are executed by the REPL session, with what they print shown as the message.
+    fn execute(&mut self, command: &str) {
+        if let Err(e) = self.try_execute(command) {
+            self.message = e;
+        }
+    }
+
+    fn try_execute(&mut self, command: &str) -> Result<(), String> {
+        let parts = repl::tokenize(command)?;
+
+        match parts.first().copied().unwrap_or("") {
+            "" => {}
+            "step" | "s" => {
+                repl::syntax(parts.len() <= 2)?;
+                let count = parts.get(1).map_or(Ok(1), |c| repl::positive_count(c))?;
+                self.start(Run::Steps(count));
+            }
+            "next" | "n" => {
+                repl::syntax(parts.len() == 1)?;
+                self.next();
+            }
+            "finish" => {
+                repl::syntax(parts.len() == 1)?;
+                self.finish();
+            }
+            "until" | "u" => {
+                repl::syntax(parts.len() == 2)?;
+                match repl::address(parts[1])? {
+                    address if address < self.interp().code().len() => self.start(Run::Until(address)),
+                    address => return Err(format!("Address out of bounds: {}", address)),
+                }
+            }
+            "continue" | "c" | "run" | "r" => {
+                repl::syntax(parts.len() == 1)?;
+                self.start(Run::Continue);
+            }
+            _ => {
+                let output = self.session.execute(command)?;
+                self.message = output.lines().collect::<Vec<_>>().join("  ");
+                self.quit = self.session.has_quit();
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the next chunk of the program if it is running.
+    fn tick(&mut self) {
+        let run = match self.running {
+            Some(run) => run,
+            None => return,
+        };
+
+        let steps = self.interp().steps();
+        let (reason, next) = match run {
+            Run::Continue => (self.interp_mut().step_many(RUN_CHUNK), Some(run)),
+            Run::Until(address) => {
+                let reason = self.interp_mut().step_until(address, RUN_CHUNK);
+                let arrived = self.interp().instruction_pointer() == address;
+                (reason, Some(run).filter(|_| !arrived))
+            }
+            Run::Steps(count) => {
+                let reason = self.interp_mut().step_many(count.min(RUN_CHUNK));
+                let remaining = count - (self.interp().steps() - steps);
+                (reason, Some(Run::Steps(remaining)).filter(|_| remaining > 0))
+            }
+        };
+
+        match next {
+            Some(next) if reason == StopReason::Stepped => self.running = Some(next),
+            _ => {
+                self.running = None;
+                self.report(reason);
+            }
+        }
+    }
+
+    /// Lays out the whole screen. Every Line is exactly `width` characters wide.
+    fn render(&self, width: usize, height: usize) -> Vec<Line> {
+        let output_height = OUTPUT_HEIGHT.min(height / 3);
+        let top_height = height.saturating_sub(output_height + 2);
+        let left_width = width * 2 / 3;
+        let right_width = width.saturating_sub(left_width + 1);
+        let tape_height = top_height - top_height / 3;
+
+        let mut right = self.tape_pane(right_width, tape_height);
+        right.extend(self.breakpoints_pane(right_width, top_height - tape_height));
+
+        let mut result: Vec<Line> = self
+            .source_pane(left_width, top_height)
+            .into_iter()
+            .zip(right)
+            .map(|(mut left, right)| {
+                left.push(Span::plain("|"));
+                left.extend(right);
+                left
+            })
+            .collect();
+        result.extend(self.output_pane(width, output_height));
+
+        let status = format!(
+            " steps {}  ip {}  dp {}  |  {}",
+            self.interp().steps(),
+            self.interp().instruction_pointer(),
+            self.interp().data_pointer(),
+            KEYS
+        );
+        result.push(fit(vec![Span::highlighted(status)], width));
+        let bottom = match &self.command {
+            Some(command) => format!(":{}", command),
+            None => self.message.clone(),
+        };
+        result.push(fit(vec![Span::plain(bottom)], width));
+
+        result.truncate(height);
+        result
+    }
+
+    fn source_pane(&self, width: usize, height: usize) -> Vec<Line> {
+        let mut result = vec![fit(vec![Span::highlighted(" Source")], width)];
+        let rows = height.saturating_sub(1);
+
+        let current = self.positions.get(self.interp().instruction_pointer());
+        let breakpoint_lines: Vec<usize> = self
+            .interp()
+            .breakpoints()
+            .iter()
+            .filter_map(|(address, _)| self.positions.get(*address).map(|p| p.line))
+            .collect();
+
+        // The whole pane scrolls sideways to keep the current instruction in view.
+        let gutter = 6;
+        let visible = width.saturating_sub(gutter);
+        let offset = match current {
+            Some(position) if position.column > visible => (position.column - visible / 2).min(position.column - 1),
+            _ => 0,
+        };
+        let center = current.map_or(1, |position| position.line);
+        let first = center.saturating_sub(rows / 2).max(1);
+
+        for (index, line) in self.source.lines().enumerate().skip(first - 1).take(rows) {
+            let number = index + 1;
+            let marker = if breakpoint_lines.contains(&number) { '*' } else { ' ' };
+            let chars: Vec<char> = line
+                .chars()
+                .map(|c| if c.is_control() { ' ' } else { c })
+                .skip(offset)
+                .collect();
+            let mut spans = vec![Span::plain(format!("{:>4}{} ", number, marker))];
+
+            match current.filter(|position| position.line == number) {
+                Some(position) => {
+                    let column = position.column - 1 - offset;
+                    spans.push(Span::plain(chars[..column].iter().collect::<String>()));
+                    spans.push(Span::highlighted(chars[column].to_string()));
+                    spans.push(Span::plain(chars[column + 1..].iter().collect::<String>()));
+                }
+                None => spans.push(Span::plain(chars.iter().collect::<String>())),
+            }
+            result.push(fit(spans, width));
+        }
+
+        pad(result, width, height)
+    }
+
+    fn tape_pane(&self, width: usize, height: usize) -> Vec<Line> {
+        let mut result = vec![fit(vec![Span::highlighted(" Tape")], width)];
+        let rows = height.saturating_sub(1);
+
+        let tape = self.interp().tape();
+        let data_pointer = self.interp().data_pointer();
+        let first = data_pointer.saturating_sub(rows / 2).min(tape.len().saturating_sub(rows));
+
+        for (address, value) in tape.iter().enumerate().skip(first).take(rows) {
+            let c = if value.is_ascii_graphic() { *value as char } else { '.' };
+            let text = format!("{:<width$}", format!("{:>6}  {:>3}  {:02x}  {}", address, value, value, c), width = width);
+            let span = if address == data_pointer {
+                Span::highlighted(text)
+            } else {
+                Span::plain(text)
+            };
+            result.push(fit(vec![span], width));
+        }
+
+        pad(result, width, height)
+    }
+
+    fn breakpoints_pane(&self, width: usize, height: usize) -> Vec<Line> {
+        let mut result = vec![fit(vec![Span::highlighted(" Breakpoints")], width)];
+        let breakpoints = self.interp().breakpoints();
+
+        if breakpoints.is_empty() {
+            result.push(fit(vec![Span::plain(" (none)")], width));
+        }
+        for (address, breakpoint) in breakpoints {
+            let mut text = format!(" {}", self.location(address));
+            if let Some(condition) = &breakpoint.condition {
+                text.push_str(&format!(" if {}", condition));
+            }
+            result.push(fit(vec![Span::plain(text)], width));
+        }
+
+        result.truncate(height);
+        pad(result, width, height)
+    }
+
+    fn output_pane(&self, width: usize, height: usize) -> Vec<Line> {
+        let mut result = vec![fit(vec![Span::highlighted(" Output")], width)];
+        let rows = height.saturating_sub(1);
+
+        let output = String::from_utf8_lossy(&self.session.output()).into_owned();
+        let lines: Vec<&str> = output.split('\n').collect();
+        for line in &lines[lines.len().saturating_sub(rows)..] {
+            let line: String = line.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+            result.push(fit(vec![Span::plain(line)], width));
+        }
+
+        result.truncate(height);
+        pad(result, width, height)
+    }
+}
+
+/// Truncates or pads a Line with spaces so that it is exactly `width` characters wide.
+fn fit(spans: Line, width: usize) -> Line {
+    let mut result = Vec::new();
+    let mut remaining = width;
+
+    for span in spans {
+        let text: String = span.text.chars().take(remaining).collect();
+        remaining -= text.chars().count();
+        if !text.is_empty() {
+            result.push(Span { text, ..span });
+        }
+    }
+    if remaining > 0 {
+        result.push(Span::plain(" ".repeat(remaining)));
+    }
+
+    result
+}
+
+/// Adds blank Lines until there are `height` of them.
+fn pad(mut lines: Vec<Line>, width: usize, height: usize) -> Vec<Line> {
+    while lines.len() < height {
+        lines.push(fit(Vec::new(), width));
+    }
+    lines
+}
+
+fn draw(out: &mut impl Write, lines: &[Line]) -> io::Result<()> {
+    for (row, line) in lines.iter().enumerate() {
+        queue!(out, MoveTo(0, row as u16))?;
+        for span in line {
+            if span.highlight {
+                queue!(out, SetAttribute(Attribute::Reverse), Print(&span.text), SetAttribute(Attribute::Reset))?;
+            } else {
+                queue!(out, Print(&span.text))?;
+            }
+        }
+    }
+    out.flush()
+}
+
+fn event_loop(app: &mut App, out: &mut impl Write) -> io::Result<()> {
+    while !app.quit {
+        let (width, height) = terminal::size()?;
+        draw(out, &app.render(width as usize, height as usize))?;
+
+        // While the program runs, key presses are only checked for between chunks of execution.
+        if app.running.is_none() || event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                app.handle_key(key);
+            }
+        }
+        app.tick();
+    }
+    Ok(())
+}
+
+/// Puts the terminal back the way it was when dropped, even if the debugger panics.
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Runs the full-screen debugger until the user quits.
+///
+/// # Arguments
+///
+/// * `source` - The Brainfuck source code to debug.
+/// * `input` - The input given to the program.
+pub fn tui(source: String, input: Vec<u8>) -> io::Result<()> {
+    let mut app = App::new(source, input);
+    let mut stdout = io::stdout();
+
+    terminal::enable_raw_mode()?;
+    let _guard = TerminalGuard;
+    execute!(stdout, EnterAlternateScreen, Hide)?;
+    event_loop(&mut app, &mut stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let code = if c == '\n' { KeyCode::Enter } else { KeyCode::Char(c) };
+            app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+        }
+    }
+
+    fn text(line: &Line) -> String {
+        line.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    #[test]
+    fn keys_step_through_the_program() {
+        let mut app = App::new(String::from("++\n[>+<-]\n>."), Vec::new());
+
+        press(&mut app, "ss");
+        assert_eq!(app.message, "Stopped at 2 (2:1)");
+        press(&mut app, "n");
+        app.tick();
+        assert_eq!(app.message, "Stopped at 8 (3:1)");
+        assert_eq!(app.interp().tape()[1], 2);
+        press(&mut app, "f");
+        assert_eq!(app.message, "Not inside a loop");
+        press(&mut app, "ss");
+        assert_eq!(app.message, "Program finished");
+        assert_eq!(app.session.output(), vec![2]);
+    }
+
+    #[test]
+    fn continue_runs_until_a_breakpoint_or_a_key_press() {
+        let mut app = App::new(String::from("+[>+<]"), Vec::new());
+
+        press(&mut app, ":b  3\n");
+        assert_eq!(app.message, "OK");
+        press(&mut app, "c");
+        app.tick();
+        assert_eq!(app.running, None);
+        assert_eq!(app.message, "Hit breakpoint at 3 (1:4), 1 time(s)");
+
+        press(&mut app, "bc");
+        app.tick();
+        assert_eq!(app.running, Some(Run::Continue));
+        press(&mut app, "x");
+        assert_eq!(app.running, None);
+        assert!(app.message.starts_with("Paused at "), "{}", app.message);
+    }
+
+    #[test]
+    fn stepping_runs_in_chunks_that_a_key_press_pauses() {
+        let mut app = App::new(String::from("+[[]]"), Vec::new());
+
+        press(&mut app, "sn");
+        app.tick();
+        assert_eq!(app.running, Some(Run::Until(5)));
+        press(&mut app, "x");
+        assert_eq!(app.running, None);
+        assert!(app.message.starts_with("Paused at "), "{}", app.message);
+
+        press(&mut app, "sf");
+        app.tick();
+        assert_eq!(app.running, Some(Run::Until(4)));
+        press(&mut app, "x:until 4\n");
+        app.tick();
+        assert_eq!(app.running, Some(Run::Until(4)));
+        press(&mut app, "x:step 250000\n");
+        app.tick();
+        app.tick();
+        assert_eq!(app.running, Some(Run::Steps(50_000)));
+        app.tick();
+        assert_eq!(app.running, None);
+        assert_eq!(app.interp().steps(), 2 + 3 * RUN_CHUNK + 250_000);
+    }
+
+    #[test]
+    fn source_pane_handles_narrow_screens() {
+        let mut app = App::new(String::from("++++++++."), Vec::new());
+        press(&mut app, "sssss");
+
+        for width in 0..10 {
+            let pane = app.source_pane(width, 3);
+            assert!(pane.iter().all(|line| text(line).chars().count() == width));
+        }
+    }
+
+    #[test]
+    fn command_line_reports_errors() {
+        let mut app = App::new(String::from("+"), Vec::new());
+
+        press(&mut app, ":b 5\n");
+        assert_eq!(app.message, "Address out of bounds: 5");
+        press(&mut app, ":until 5\n");
+        assert_eq!(app.message, "Address out of bounds: 5");
+        press(&mut app, ":frobnicate\n");
+        assert_eq!(app.message, "Unrecognized command: frobnicate");
+        press(&mut app, ":step 0\n");
+        assert_eq!(app.message, "Invalid count: 0");
+        press(&mut app, ":continue 2\n");
+        assert_eq!(app.message, "Invalid syntax!");
+    }
+
+    #[test]
+    fn command_line_executes_repl_commands() {
+        let mut app = App::new(String::from("+[>+<-]"), Vec::new());
+
+        press(&mut app, ":watch 1\n:assign  0  3\n:print 0\n");
+        assert_eq!(app.message, "3");
+        press(&mut app, ":c\n");
+        app.tick();
+        assert_eq!(app.message, "Hit watchpoint on cell 1 at 4 (1:5)");
+        press(&mut app, ":q\n");
+        assert!(app.quit);
+    }
+
+    #[test]
+    fn render_fills_the_screen_and_highlights_the_current_instruction() {
+        let mut app = App::new(String::from("hello\n,[.,]"), b"hi\0".to_vec());
+        app.interp_mut().set_breakpoint(4).unwrap();
+        press(&mut app, "sss");
+
+        let screen = app.render(60, 20);
+
+        assert_eq!(screen.len(), 20);
+        assert!(screen.iter().all(|line| text(line).chars().count() == 60));
+        assert_eq!(text(&screen[1]).trim_end(), format!("{:<40}|{}", "   1  hello", "     0  104  68  h"));
+        assert_eq!(text(&screen[2]).trim_end(), format!("{:<40}|{}", "   2* ,[.,]", "     1    0  00  ."));
+        assert!(screen[2].contains(&Span::highlighted(",")));
+        assert!(screen[1].contains(&Span::highlighted("     0  104  68  h ")));
+        assert_eq!(text(&screen[9]).trim_end(), format!("{:<40}| 4 (2:5)", ""));
+        assert_eq!(text(&screen[13]).trim_end(), "h");
+        assert!(text(&screen[18]).starts_with(" steps 3  ip 3  dp 0  |"));
+        assert_eq!(text(&screen[19]).trim_end(), "Stopped at 3 (2:4)");
+    }
+}
@@ -0,0 +1,64 @@
+use bfkit::golden::{self, Backend, Case, Options};
+use std::path::Path;
+use std::process::Command;
+
+/// Programs that take too long to interpret in debug builds.
+/// Run them with `cargo test -- --ignored`.
+const SLOW: &[&str] = &["hanoi", "life", "mandelbrot"];
+
+fn cases() -> Vec<Case> {
+    golden::discover(&Path::new(env!("CARGO_MANIFEST_DIR")).join("test")).unwrap()
+}
+
+fn check_all(cases: &[Case], backend: Backend) {
+    let options = Options::default();
+
+    let failures: Vec<String> = cases
+        .iter()
+        .filter_map(|case| {
+            golden::check(case, backend, &options)
+                .err()
+                .map(|e| format!("{} ({}): {}", case.name, backend, e))
+        })
+        .collect();
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn every_test_program_has_expected_output() {
+    let cases = cases();
+
+    for entry in Path::new(env!("CARGO_MANIFEST_DIR")).join("test").read_dir().unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() == Some("bf".as_ref()) {
+            assert!(cases.iter().any(|c| c.path == path), "No golden output for {}", path.display());
+        }
+    }
+}
+
+#[test]
+fn interpreter_matches_golden_output() {
+    let cases: Vec<Case> = cases().into_iter().filter(|c| !SLOW.contains(&c.name.as_str())).collect();
+
+    check_all(&cases, Backend::Interpreter);
+}
+
+#[test]
+#[ignore]
+fn interpreter_matches_golden_output_of_slow_programs() {
+    let cases: Vec<Case> = cases().into_iter().filter(|c| SLOW.contains(&c.name.as_str())).collect();
+
+    check_all(&cases, Backend::Interpreter);
+}
+
+#[test]
+fn c_backend_matches_golden_output() {
+    let compiler = Options::default().compiler;
+    if Command::new(&compiler).arg("--version").output().is_err() {
+        eprintln!("Skipping C backend golden tests: {} not found", compiler);
+        return;
+    }
+
+    check_all(&cases(), Backend::C);
+}